@@ -4,7 +4,7 @@
 use anyhow::Result;
 use std::path::Path;
 
-use crate::{Backend, BpfOption, EventNode, ReportDescriptor};
+use crate::{Backend, BpfOption, Event, EventNode, ReportDescriptor};
 
 #[derive(Debug)]
 pub struct BinaryBackend {
@@ -56,7 +56,12 @@ impl Backend for BinaryBackend {
         &[]
     }
 
-    fn read_events(&self, _use_bpf: BpfOption, _rdesc: &ReportDescriptor) -> Result<()> {
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        _rdesc: &ReportDescriptor,
+        _callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
         Ok(())
     }
 }
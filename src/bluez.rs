@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: MIT
+//
+// Reads the report map of a HID over GATT device from BlueZ's device
+// cache in /var/lib/bluetooth/<adapter>/cache/<address>, e.g.
+//
+// [General]
+// Name=BLE Mouse
+//
+// [Attributes]
+// 0028=00002800-0000-1000-8000-00805f9b34fb:0028:003a:1812
+// 002b=00002803-0000-1000-8000-00805f9b34fb:002c:02:05010902a101...c0:00002a4b-0000-1000-8000-00805f9b34fb
+//
+// The characteristic declarations contain the characteristic's value
+// (where BlueZ cached it) before the characteristic's UUID.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{Backend, BpfOption, Event, EventNode, ReportDescriptor};
+
+/// Report Map characteristic of the HID Service
+const REPORT_MAP_UUID: u16 = 0x2a4b;
+/// PnP ID characteristic of the Device Information Service
+const PNP_ID_UUID: u16 = 0x2a50;
+
+pub struct BluezCacheBackend {
+    name: String,
+    vid: u32,
+    pid: u32,
+    rdesc: Vec<u8>,
+}
+
+/// The key/value pairs of each section of a BlueZ key file
+fn parse_key_file(data: &str) -> HashMap<String, HashMap<String, String>> {
+    let mut sections: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut section = String::new();
+    for line in data.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            sections
+                .entry(section.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    sections
+}
+
+/// The 16-bit UUID of a Bluetooth SIG UUID, either in its short form
+/// `2a4b` or as `00002a4b-0000-1000-8000-00805f9b34fb`
+fn uuid16(uuid: &str) -> Option<u16> {
+    let short = match uuid.len() {
+        4 => uuid,
+        36 => uuid
+            .to_lowercase()
+            .strip_suffix("-0000-1000-8000-00805f9b34fb")?
+            .strip_prefix("0000")
+            .map(|_| &uuid[4..8])?,
+        _ => return None,
+    };
+    u16::from_str_radix(short, 16).ok()
+}
+
+/// The cached value of the characteristic with this UUID, if any
+fn characteristic_value(attributes: &HashMap<String, String>, uuid: u16) -> Option<Vec<u8>> {
+    attributes.values().find_map(|value| {
+        let parts: Vec<&str> = value.split(':').collect();
+        match parts.as_slice() {
+            [.., value, chrc_uuid] if uuid16(chrc_uuid) == Some(uuid) && value.len() > 4 => {
+                hex::decode(value).ok()
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Whether `data` looks like a BlueZ device cache, i.e. has an
+/// `[Attributes]` section
+pub fn is_bluez_cache(data: &[u8]) -> bool {
+    String::from_utf8_lossy(data)
+        .lines()
+        .any(|l| l.trim() == "[Attributes]")
+}
+
+impl TryFrom<&Path> for BluezCacheBackend {
+    type Error = anyhow::Error;
+
+    fn try_from(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let cache = parse_key_file(&data);
+        let attributes = cache
+            .get("Attributes")
+            .context("Not a BlueZ device cache")?;
+        let Some(rdesc) = characteristic_value(attributes, REPORT_MAP_UUID) else {
+            bail!("No cached Report Map characteristic found");
+        };
+
+        let mut name = cache
+            .get("General")
+            .and_then(|general| general.get("Name"))
+            .cloned();
+        let (mut vid, mut pid) = (0, 0);
+
+        // PnP ID: vendor ID source, vendor ID, product ID, product version
+        if let Some(pnp_id) = characteristic_value(attributes, PNP_ID_UUID) {
+            if let [_, v0, v1, p0, p1, ..] = pnp_id[..] {
+                vid = u32::from(u16::from_le_bytes([v0, v1]));
+                pid = u32::from(u16::from_le_bytes([p0, p1]));
+            }
+        }
+
+        // /var/lib/bluetooth/<adapter>/<address>/info has the Device ID,
+        // and the name if the cache does not have it
+        let info = path
+            .parent()
+            .filter(|cache| cache.file_name().is_some_and(|n| n == "cache"))
+            .and_then(|cache| cache.parent())
+            .zip(path.file_name())
+            .map(|(adapter, address)| adapter.join(address).join("info"))
+            .and_then(|info| std::fs::read_to_string(info).ok())
+            .map(|info| parse_key_file(&info));
+        if let Some(info) = info {
+            if name.is_none() {
+                name = info
+                    .get("General")
+                    .and_then(|general| general.get("Name"))
+                    .cloned();
+            }
+            if let Some(device_id) = info.get("DeviceID").filter(|_| vid == 0 && pid == 0) {
+                let id = |key| device_id.get(key).and_then(|v| v.parse::<u32>().ok());
+                vid = id("Vendor").unwrap_or(0);
+                pid = id("Product").unwrap_or(0);
+            }
+        }
+
+        Ok(BluezCacheBackend {
+            name: name.unwrap_or(String::from("No Name")),
+            vid,
+            pid,
+            rdesc,
+        })
+    }
+}
+
+impl Backend for BluezCacheBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bustype(&self) -> u32 {
+        // BUS_BLUETOOTH
+        0x05
+    }
+
+    fn vid(&self) -> u32 {
+        self.vid
+    }
+
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn rdesc(&self) -> &[u8] {
+        &self.rdesc
+    }
+
+    fn event_nodes(&self) -> &[EventNode] {
+        &[]
+    }
+
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        _rdesc: &ReportDescriptor,
+        _callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A BLE mouse with the PnP ID in the Device Information service
+    // (0x180a) and its descriptor as Report Map value in the HID service
+    // (0x1812)
+    const CACHE: &str = "\
+[General]
+Name=BLE Mouse
+
+[ServiceRecords]
+
+[Attributes]
+0001=00002800-0000-1000-8000-00805f9b34fb:0001:0005:1800
+0002=00002803-0000-1000-8000-00805f9b34fb:0003:02:00002a00-0000-1000-8000-00805f9b34fb
+0020=00002800-0000-1000-8000-00805f9b34fb:0020:0022:180a
+0021=00002803-0000-1000-8000-00805f9b34fb:0022:02:026d0477c01001:00002a50-0000-1000-8000-00805f9b34fb
+0028=00002800-0000-1000-8000-00805f9b34fb:0028:003a:1812
+002b=00002803-0000-1000-8000-00805f9b34fb:002c:02:05010902a1010901a100050919012903150025019503750181029501750581010501093009311581257f750895028106c0c0:00002a4b-0000-1000-8000-00805f9b34fb
+002d=00002803-0000-1000-8000-00805f9b34fb:002e:12:00002a4d-0000-1000-8000-00805f9b34fb
+";
+
+    const INFO: &str = "\
+[General]
+Name=BLE Mouse (info)
+
+[DeviceID]
+Source=2
+Vendor=1133
+Product=45088
+Version=1
+";
+
+    #[test]
+    fn test_bluez_cache() {
+        assert_eq!(uuid16("2a4b"), Some(0x2a4b));
+        assert_eq!(uuid16("00002A4B-0000-1000-8000-00805F9B34FB"), Some(0x2a4b));
+        assert_eq!(uuid16("12345678-0000-1000-8000-00805f9b34fb"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let adapter = dir.path().join("00:11:22:33:44:55");
+        let address = "AA:BB:CC:DD:EE:FF";
+        std::fs::create_dir_all(adapter.join("cache")).unwrap();
+        let cache_path = adapter.join("cache").join(address);
+        std::fs::write(&cache_path, CACHE).unwrap();
+
+        let backend = BluezCacheBackend::try_from(cache_path.as_path()).unwrap();
+        assert_eq!(backend.name(), "BLE Mouse");
+        assert_eq!(backend.bustype(), 0x05);
+        assert_eq!((backend.vid(), backend.pid()), (0x046d, 0xc077));
+        assert_eq!(backend.rdesc().len(), 50);
+        assert!(ReportDescriptor::try_from(backend.rdesc()).is_ok());
+
+        // Without the PnP ID the Device ID comes from the info file
+        std::fs::write(
+            &cache_path,
+            CACHE.replace(":026d0477c01001:00002a50", ":00002a50"),
+        )
+        .unwrap();
+        std::fs::create_dir_all(adapter.join(address)).unwrap();
+        std::fs::write(adapter.join(address).join("info"), INFO).unwrap();
+        let backend = BluezCacheBackend::try_from(cache_path.as_path()).unwrap();
+        assert_eq!(backend.name(), "BLE Mouse");
+        assert_eq!((backend.vid(), backend.pid()), (1133, 45088));
+
+        assert!(is_bluez_cache(CACHE.as_bytes()));
+        assert!(!is_bluez_cache(INFO.as_bytes()));
+        let no_cache = dir.path().join("info");
+        std::fs::write(&no_cache, INFO).unwrap();
+        assert!(BluezCacheBackend::try_from(no_cache.as_path()).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+// SPDX-License-Identifier: MIT
+//
+// Generate the C source of a udev-hid-bpf style HID-BPF program that
+// fixes up a device's report descriptor.
+
+use anyhow::{bail, Result};
+use std::fmt::Write;
+
+use crate::export::{c_array_items, ExportFormat};
+use crate::{walk_rdesc_items, Backend};
+
+/// Byte-wise patches are easier to review, but beyond this many changed
+/// bytes we replace the whole report descriptor.
+const MAX_PATCHES: usize = 32;
+
+/// Parse a byte patch in the form `offset:value`, e.g. `12:0x26`
+pub fn parse_patch(s: &str) -> Result<(usize, u8)> {
+    let Some((offset, value)) = s.split_once(':') else {
+        bail!("Invalid patch \"{s}\", expected <offset>:<value>");
+    };
+    let parse = |s: &str| match s.trim().strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.trim().parse::<usize>(),
+    };
+    let offset = parse(offset)?;
+    let Ok(value) = u8::try_from(parse(value)?) else {
+        bail!("Invalid patch \"{s}\", the value must be a byte");
+    };
+    Ok((offset, value))
+}
+
+/// Apply the patches to a copy of the report descriptor
+pub fn apply_patches(rdesc: &[u8], patches: &[(usize, u8)]) -> Result<Vec<u8>> {
+    let mut bytes = rdesc.to_vec();
+    for (offset, value) in patches {
+        match bytes.get_mut(*offset) {
+            Some(b) => *b = *value,
+            None => bail!(
+                "Patch offset {offset} is beyond the report descriptor length of {} bytes",
+                rdesc.len()
+            ),
+        }
+    }
+    Ok(bytes)
+}
+
+fn bus_to_str(bustype: u32) -> String {
+    match bustype {
+        0x03 => "BUS_USB".into(),
+        0x05 => "BUS_BLUETOOTH".into(),
+        0x18 => "BUS_I2C".into(),
+        0x1f => "BUS_SPI".into(),
+        b => format!("0x{b:04x}"),
+    }
+}
+
+/// The offset and description of each item, see [walk_rdesc_items]
+fn item_descriptions(bytes: &[u8]) -> Vec<(usize, String)> {
+    let mut items = Vec::new();
+    let _ = walk_rdesc_items(bytes, |rdesc_item, description, _| {
+        items.push((rdesc_item.offset(), description.to_string()));
+    });
+    items
+}
+
+/// The description of the item that contains the byte at this offset
+fn item_at(items: &[(usize, String)], offset: usize) -> Option<&str> {
+    items
+        .iter()
+        .rev()
+        .find(|(start, _)| *start <= offset)
+        .map(|(_, description)| description.as_str())
+}
+
+/// Generate the HID-BPF program that turns the backend's `original`
+/// report descriptor into `target`.
+pub fn generate(backend: &impl Backend, original: &[u8], target: &[u8]) -> Result<String> {
+    if original == target {
+        bail!("The report descriptors are identical, there is nothing to fix");
+    }
+    let (vid, pid) = (backend.vid(), backend.pid());
+    let name = format!("{vid:04x}_{pid:04x}");
+    let vid_define = format!("VID_{vid:04X}");
+    let pid_define = format!("PID_{pid:04X}");
+
+    let changes: Vec<usize> = if original.len() == target.len() {
+        (0..original.len())
+            .filter(|i| original[*i] != target[*i])
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let use_patches = !changes.is_empty() && changes.len() <= MAX_PATCHES;
+
+    let mut s = String::new();
+    let _ = write!(
+        s,
+        r#"// SPDX-License-Identifier: GPL-2.0-only
+/*
+ * Report descriptor fixup for {device}
+ *
+ * Generated by hid-recorder, to be built as part of udev-hid-bpf, e.g.
+ * as src/bpf/testing/0010-{filename}.bpf.c
+ */
+
+#include "vmlinux.h"
+#include "hid_bpf.h"
+#include "hid_bpf_helpers.h"
+#include <bpf/bpf_tracing.h>
+
+#define {vid_define} 0x{vid:04X}
+#define {pid_define} 0x{pid:04X}
+
+HID_BPF_CONFIG(
+	HID_DEVICE({bus}, HID_GROUP_ANY, {vid_define}, {pid_define})
+);
+
+#define ORIGINAL_RDESC_SIZE {original_len}
+
+"#,
+        device = backend.name(),
+        filename = backend
+            .name()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+        bus = bus_to_str(backend.bustype()),
+        original_len = original.len(),
+    );
+
+    if !use_patches {
+        let _ = write!(
+            s,
+            "static const __u8 fixed_rdesc[] = {{\n{}}};\n\n",
+            c_array_items(target, ExportFormat::C)
+        );
+    }
+
+    let _ = write!(
+        s,
+        r#"SEC(HID_BPF_RDESC_FIXUP)
+int BPF_PROG(hid_fix_rdesc_{name}, struct hid_bpf_ctx *hctx)
+{{
+	__u8 *data = hid_bpf_get_data(hctx, 0 /* offset */, HID_MAX_DESCRIPTOR_SIZE /* size */);
+
+	if (!data)
+		return 0; /* EPERM check */
+
+	if (hctx->size != ORIGINAL_RDESC_SIZE)
+		return 0;
+
+"#
+    );
+
+    if use_patches {
+        let original_items = item_descriptions(original);
+        let target_items = item_descriptions(target);
+        for offset in changes.iter() {
+            let comment = match (
+                item_at(&original_items, *offset),
+                item_at(&target_items, *offset),
+            ) {
+                (Some(from), Some(to)) => format!(" /* {from} -> {to} */"),
+                _ => String::new(),
+            };
+            let _ = writeln!(s, "\tdata[{offset}] = 0x{:02x};{comment}", target[*offset]);
+        }
+        let _ = writeln!(s, "\n\treturn 0;\n}}\n");
+    } else {
+        let _ = writeln!(
+            s,
+            "\t__builtin_memcpy(data, fixed_rdesc, sizeof(fixed_rdesc));\n\n\treturn sizeof(fixed_rdesc);\n}}\n"
+        );
+    }
+
+    let _ = write!(
+        s,
+        r#"HID_BPF_OPS(fix_rdesc_{name}) = {{
+	.hid_rdesc_fixup = (void *)hid_fix_rdesc_{name},
+}};
+
+SEC("syscall")
+int probe(struct hid_bpf_probe_args *ctx)
+{{
+	ctx->retval = -EINVAL;
+
+	if (ctx->rdesc_size != ORIGINAL_RDESC_SIZE)
+		return 0;
+
+"#
+    );
+
+    // Check the original bytes we patch (or a few bytes of the descriptor)
+    // so we don't attach to a device with a different firmware
+    let checked: Vec<usize> = if use_patches {
+        changes.clone()
+    } else {
+        (0..original.len())
+            .step_by(((original.len() + 7) / 8).max(1))
+            .collect()
+    };
+    let conditions = checked
+        .iter()
+        .map(|offset| format!("ctx->rdesc[{offset}] != 0x{:02x}", original[*offset]))
+        .collect::<Vec<String>>()
+        .join(" ||\n\t    ");
+    let _ = write!(
+        s,
+        r#"	if ({conditions})
+		return 0;
+
+	ctx->retval = 0;
+
+	return 0;
+}}
+
+char _license[] SEC("license") = "GPL";
+"#
+    );
+
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::BinaryBackend;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_generate_fixup() {
+        // Offsets 15, 21 and 29 are Usage Maximum (3), Report Count (3)
+        // and the padding's Report Size (5)
+        let original = [
+            0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01,
+            0x29, 0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01,
+            0x75, 0x05, 0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f,
+            0x75, 0x08, 0x95, 0x02, 0x81, 0x06, 0xc0, 0xc0,
+        ];
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&original).unwrap();
+        file.flush().unwrap();
+        let backend = BinaryBackend::try_from(file.path()).unwrap();
+
+        assert_eq!(parse_patch("15:0x05").unwrap(), (15, 5));
+        assert!(parse_patch("15:256").is_err());
+        assert!(apply_patches(&original, &[(50, 0)]).is_err());
+
+        // 5 buttons instead of 3
+        let target = apply_patches(&original, &[(15, 0x05), (21, 0x05), (29, 0x03)]).unwrap();
+        let source = generate(&backend, &original, &target).unwrap();
+        assert!(source.contains("#define ORIGINAL_RDESC_SIZE 50"));
+        assert!(source.contains("\tdata[15] = 0x05; /* Usage Maximum (3) -> Usage Maximum (5) */"));
+        assert!(source.contains("ctx->rdesc[15] != 0x03 ||"));
+        assert!(!source.contains("fixed_rdesc"));
+
+        // A different length needs the whole descriptor
+        let source = generate(&backend, &original, &original[..48]).unwrap();
+        assert!(source.contains("static const __u8 fixed_rdesc[] = {"));
+        assert!(
+            source.contains("\t    0x81, 0x06,                          // Input (Data,Var,Rel)")
+        );
+        assert!(source.contains("return sizeof(fixed_rdesc);"));
+
+        assert!(generate(&backend, &original, &original).is_err());
+    }
+}
@@ -0,0 +1,490 @@
+// SPDX-License-Identifier: MIT
+//
+// Compile the human-readable items as printed in the report descriptor
+// listing, e.g. "Usage Page (Generic Desktop)", back into report
+// descriptor bytes.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+
+/// The item type bits of the item header, see Section 6.2.2.2
+const MAIN: u8 = 0b00;
+const GLOBAL: u8 = 0b01;
+const LOCAL: u8 = 0b10;
+
+/// Encode a short item with the smallest data size that holds `data`
+fn short_item(tag: u8, item_type: u8, data: &[u8]) -> Vec<u8> {
+    let size = match data.len() {
+        0 => 0,
+        1 => 1,
+        2 => 2,
+        4 => 3,
+        n => panic!("Invalid item data size {n}"),
+    };
+    let mut bytes = vec![(tag << 4) | (item_type << 2) | size];
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+fn unsigned_data(value: u32) -> Vec<u8> {
+    if value <= 0xff {
+        vec![value as u8]
+    } else if value <= 0xffff {
+        (value as u16).to_le_bytes().to_vec()
+    } else {
+        value.to_le_bytes().to_vec()
+    }
+}
+
+fn signed_data(value: i32) -> Vec<u8> {
+    if let Ok(v) = i8::try_from(value) {
+        v.to_le_bytes().to_vec()
+    } else if let Ok(v) = i16::try_from(value) {
+        v.to_le_bytes().to_vec()
+    } else {
+        value.to_le_bytes().to_vec()
+    }
+}
+
+/// Parse a decimal or 0x-prefixed hexadecimal number
+fn parse_number(s: &str) -> Result<i64> {
+    let s = s.trim();
+    let value = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else {
+        s.parse::<i64>()
+    };
+    value.with_context(|| format!("Invalid number \"{s}\""))
+}
+
+fn parse_u32(s: &str) -> Result<u32> {
+    let v = parse_number(s)?;
+    u32::try_from(v).with_context(|| format!("{v} is out of range"))
+}
+
+/// Minimums are always signed
+fn signed_value(s: &str) -> Result<Vec<u8>> {
+    let v = parse_number(s)?;
+    let v = i32::try_from(v).with_context(|| format!("{v} is out of range"))?;
+    Ok(signed_data(v))
+}
+
+/// Maximums are encoded so that a parser interpreting them as signed
+/// value gets the same value, e.g. 255 is 0x00ff. The special case of
+/// values above i32::MAX (the listing prints a -1 maximum as 4294967295)
+/// uses all 4 bytes.
+fn maximum_value(s: &str) -> Result<Vec<u8>> {
+    let v = parse_number(s)?;
+    match i32::try_from(v) {
+        Ok(v) => Ok(signed_data(v)),
+        Err(_) => Ok(parse_u32(s)?.to_le_bytes().to_vec()),
+    }
+}
+
+fn main_data_flags(args: &str, is_input: bool) -> Result<u32> {
+    let mut flags = 0u32;
+    for flag in args.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        flags |= match flag {
+            "Data" | "Arr" | "Abs" => 0,
+            "Cnst" | "Const" => 1 << 0,
+            "Var" => 1 << 1,
+            "Rel" => 1 << 2,
+            "Wrap" => 1 << 3,
+            "NonLin" => 1 << 4,
+            "NoPref" => 1 << 5,
+            "Null" => 1 << 6,
+            "Vol" if !is_input => 1 << 7,
+            "Buff" => 1 << 8,
+            f => match parse_u32(f) {
+                Ok(v) => v,
+                Err(_) => bail!("Unknown main item flag \"{f}\""),
+            },
+        };
+    }
+    Ok(flags)
+}
+
+fn collection_type(args: &str) -> Result<u8> {
+    Ok(match args.trim() {
+        "Physical" => 0x00,
+        "Application" => 0x01,
+        "Logical" => 0x02,
+        "Report" => 0x03,
+        "NamedArray" => 0x04,
+        "UsageSwitch" => 0x05,
+        "UsageModifier" => 0x06,
+        s => {
+            let v = parse_number(s)
+                .with_context(|| format!("Unknown collection type \"{s}\", use a number"))?;
+            u8::try_from(v).with_context(|| format!("{v} is out of range"))?
+        }
+    })
+}
+
+/// Parse a unit as printed by the listing, e.g. "SILinear: cm" or
+/// "EnglishRotation: deg·s⁻¹" back into the 32-bit unit value
+fn unit_value(args: &str) -> Result<u32> {
+    if let Ok(v) = parse_u32(args) {
+        return Ok(v);
+    }
+    let (system, units) = args.split_once(':').unwrap_or((args, ""));
+    let system: u32 = match system.trim() {
+        "None" => 0,
+        "SILinear" => 1,
+        "SIRotation" => 2,
+        "EnglishLinear" => 3,
+        "EnglishRotation" => 4,
+        s => bail!("Unknown unit system \"{s}\""),
+    };
+
+    // Longer names first so "slug" is not parsed as "s"
+    const UNITS: [(&str, u32); 11] = [
+        ("slug", 2),
+        ("rad", 1),
+        ("deg", 1),
+        ("cm", 1),
+        ("in", 1),
+        ("cd", 6),
+        ("g", 2),
+        ("s", 3),
+        ("K", 4),
+        ("F", 4),
+        ("A", 5),
+    ];
+    const SUPERSCRIPTS: [(char, i32); 10] = [
+        ('⁰', 0),
+        ('¹', 1),
+        ('²', 2),
+        ('³', 3),
+        ('⁴', 4),
+        ('⁵', 5),
+        ('⁶', 6),
+        ('⁷', 7),
+        ('⁸', 8),
+        ('⁹', 9),
+    ];
+
+    let mut value = system;
+    let mut rest = units.trim();
+    while !rest.is_empty() {
+        rest = rest.trim_start_matches(['·', '*', ' ']);
+        let Some((name, nibble)) = UNITS.iter().find(|(name, _)| rest.starts_with(name)) else {
+            bail!("Unknown unit \"{rest}\"");
+        };
+        rest = &rest[name.len()..];
+        let negative = rest.starts_with('⁻');
+        if negative {
+            rest = &rest['⁻'.len_utf8()..];
+        }
+        let mut exponent: Option<i32> = None;
+        while let Some((c, digit)) = SUPERSCRIPTS.iter().find(|(c, _)| rest.starts_with(*c)) {
+            exponent = Some(exponent.unwrap_or(0) * 10 + digit);
+            rest = &rest[c.len_utf8()..];
+        }
+        let exponent = match (exponent, negative) {
+            (None, _) => 1,
+            (Some(e), true) => -e,
+            (Some(e), false) => e,
+        };
+        if !(-8..=7).contains(&exponent) {
+            bail!("Unit exponent {exponent} for {name} is out of range");
+        }
+        value |= ((exponent as u32) & 0xf) << (nibble * 4);
+    }
+    Ok(value)
+}
+
+/// Compiles item text into report descriptor bytes, resolving usage page
+/// and usage names through the HUT.
+#[derive(Default)]
+pub struct Compiler {
+    usage_page: u16,
+    /// Usage page name to value
+    usage_pages: Option<HashMap<String, u16>>,
+    /// Per usage page, usage name to usage ID
+    usages: HashMap<u16, HashMap<String, u16>>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn usage_page_by_name(&mut self, name: &str) -> Option<u16> {
+        let pages = self.usage_pages.get_or_insert_with(|| {
+            (0..=u16::MAX)
+                .filter_map(|v| {
+                    hut::UsagePage::try_from(v)
+                        .ok()
+                        .map(|up| (format!("{up}"), v))
+                })
+                .collect()
+        });
+        pages.get(name).copied()
+    }
+
+    fn usage_by_name(&mut self, usage_page: u16, name: &str) -> Option<u16> {
+        let usages = self.usages.entry(usage_page).or_insert_with(|| {
+            (0..=u16::MAX)
+                .filter_map(|v| {
+                    hut::Usage::new_from_page_and_id(usage_page, v)
+                        .ok()
+                        .map(|u| (format!("{u}"), v))
+                })
+                .collect()
+        });
+        usages.get(name).copied()
+    }
+
+    /// Look up a usage that is not in the current usage page. Vendor
+    /// defined and reserved pages are skipped, their usage names are
+    /// generic and would match in every such page.
+    fn extended_usage_by_name(&mut self, name: &str) -> Option<u32> {
+        let pages: Vec<u16> = (0..=u16::MAX)
+            .filter(|v| {
+                matches!(
+                    hut::UsagePage::try_from(*v),
+                    Ok(up) if !matches!(
+                        up,
+                        hut::UsagePage::VendorDefinedPage(..) | hut::UsagePage::ReservedUsagePage(..)
+                    )
+                )
+            })
+            .collect();
+        pages.into_iter().find_map(|page| {
+            self.usage_by_name(page, name)
+                .map(|id| (u32::from(page) << 16) | u32::from(id))
+        })
+    }
+
+    fn usage_page_value(&mut self, args: &str) -> Result<u16> {
+        if let Ok(v) = parse_number(args) {
+            return u16::try_from(v).with_context(|| format!("{v} is out of range"));
+        }
+        // Unknown usage pages are printed as "Usage Page (Usage Page (0x1234))"
+        if let Some(inner) = args
+            .strip_prefix("Usage Page (")
+            .and_then(|s| s.strip_suffix(')'))
+        {
+            return self.usage_page_value(inner);
+        }
+        self.usage_page_by_name(args)
+            .with_context(|| format!("Unknown usage page \"{args}\""))
+    }
+
+    /// The data of a Usage, Usage Minimum or Usage Maximum item
+    fn usage_value(&mut self, args: &str) -> Result<Vec<u8>> {
+        if let Ok(v) = parse_u32(args) {
+            return Ok(unsigned_data(v));
+        }
+        if let Some(id) = self.usage_by_name(self.usage_page, args) {
+            return Ok(unsigned_data(u32::from(id)));
+        }
+        // A usage from a different page needs the 4-byte form
+        match self.extended_usage_by_name(args) {
+            Some(usage) => Ok(usage.to_le_bytes().to_vec()),
+            None => bail!("Unknown usage \"{args}\""),
+        }
+    }
+
+    /// Compile a single item, e.g. "Report Size (8)"
+    pub fn compile_item(&mut self, item: &str) -> Result<Vec<u8>> {
+        let item = item.trim();
+        let (name, args) = match item.split_once('(') {
+            Some((name, args)) => {
+                let args = args
+                    .strip_suffix(')')
+                    .with_context(|| format!("Missing closing parenthesis in \"{item}\""))?;
+                (name.trim(), args.trim())
+            }
+            None => (item, ""),
+        };
+
+        let bytes = match name {
+            "Input" => short_item(0x8, MAIN, &unsigned_data(main_data_flags(args, true)?)),
+            "Output" => short_item(0x9, MAIN, &unsigned_data(main_data_flags(args, false)?)),
+            "Feature" => short_item(0xb, MAIN, &unsigned_data(main_data_flags(args, false)?)),
+            "Collection" => short_item(0xa, MAIN, &[collection_type(args)?]),
+            "End Collection" => short_item(0xc, MAIN, &[]),
+            "Usage Page" => {
+                self.usage_page = self.usage_page_value(args)?;
+                short_item(0x0, GLOBAL, &unsigned_data(u32::from(self.usage_page)))
+            }
+            "Logical Minimum" => short_item(0x1, GLOBAL, &signed_value(args)?),
+            "Logical Maximum" => short_item(0x2, GLOBAL, &maximum_value(args)?),
+            "Physical Minimum" => short_item(0x3, GLOBAL, &signed_value(args)?),
+            "Physical Maximum" => short_item(0x4, GLOBAL, &maximum_value(args)?),
+            "Unit Exponent" => {
+                let exponent = parse_number(args)?;
+                if !(-8..=7).contains(&exponent) {
+                    bail!("Unit exponent {exponent} is out of range");
+                }
+                // Encoded as 4-bit two's complement nibble
+                short_item(0x5, GLOBAL, &[(exponent as u8) & 0xf])
+            }
+            "Unit" => short_item(0x6, GLOBAL, &unsigned_data(unit_value(args)?)),
+            "Report Size" => short_item(0x7, GLOBAL, &unsigned_data(parse_u32(args)?)),
+            "Report ID" => short_item(0x8, GLOBAL, &unsigned_data(parse_u32(args)?)),
+            "Report Count" => short_item(0x9, GLOBAL, &unsigned_data(parse_u32(args)?)),
+            "Push" => short_item(0xa, GLOBAL, &[]),
+            "Pop" => short_item(0xb, GLOBAL, &[]),
+            "Usage" => short_item(0x0, LOCAL, &self.usage_value(args)?),
+            "Usage Minimum" => short_item(0x1, LOCAL, &self.usage_value(args)?),
+            "Usage Maximum" => short_item(0x2, LOCAL, &self.usage_value(args)?),
+            "Designator Index" => short_item(0x3, LOCAL, &unsigned_data(parse_u32(args)?)),
+            "Designator Minimum" => short_item(0x4, LOCAL, &unsigned_data(parse_u32(args)?)),
+            "Designator Maximum" => short_item(0x5, LOCAL, &unsigned_data(parse_u32(args)?)),
+            "String Index" => short_item(0x7, LOCAL, &unsigned_data(parse_u32(args)?)),
+            "String Minimum" => short_item(0x8, LOCAL, &unsigned_data(parse_u32(args)?)),
+            "String Maximum" => short_item(0x9, LOCAL, &unsigned_data(parse_u32(args)?)),
+            "Delimiter" => short_item(0xa, LOCAL, &unsigned_data(parse_u32(args)?)),
+            _ => bail!("Unknown item \"{item}\""),
+        };
+        Ok(bytes)
+    }
+}
+
+/// Extract the item text from a line. This handles plain items, items
+/// followed by a `//` comment and lines copied from the report descriptor
+/// listing or a C array, where the item text is the comment following
+/// the bytes (and followed by the offset).
+fn item_text(line: &str) -> Option<&str> {
+    let line = line.trim();
+    // The listing's header line
+    if line.contains("// Field Name") {
+        return None;
+    }
+    // The R:, N:, I:, ... lines of a recording
+    if matches!(line.as_bytes(), [b'A'..=b'Z', b':', ..]) {
+        return None;
+    }
+    let text = match line.split_once("//") {
+        Some((before, after)) => {
+            // Drop the leading '#' and the item type markers
+            let before =
+                before.trim_start_matches(|c: char| c == '#' || c.is_whitespace() || !c.is_ascii());
+            let is_bytes = before
+                .split([',', ' '])
+                .filter(|s| !s.is_empty())
+                .all(|s| s.starts_with("0x"));
+            if is_bytes {
+                // Strip the offset column of the listing
+                after
+                    .trim()
+                    .trim_end_matches(|c: char| c.is_ascii_digit())
+                    .trim_end()
+            } else {
+                before.trim()
+            }
+        }
+        None if line.starts_with('#') => return None,
+        None => line,
+    };
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Compile the items in `text`, one per line, into report descriptor bytes.
+pub fn compile(text: &str) -> Result<Vec<u8>> {
+    let mut compiler = Compiler::new();
+    let mut bytes = Vec::new();
+    for (lineno, line) in text.lines().enumerate() {
+        if let Some(item) = item_text(line) {
+            let item_bytes = compiler
+                .compile_item(item)
+                .with_context(|| format!("Line {}: \"{}\"", lineno + 1, line.trim()))?;
+            bytes.extend(item_bytes);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walk_rdesc_items;
+
+    #[test]
+    fn test_compile_roundtrip() {
+        // A mouse with nested collections, a negative Logical Minimum and
+        // Cnst and Rel main items
+        let bytes = [
+            0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01,
+            0x29, 0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01,
+            0x75, 0x05, 0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f,
+            0x75, 0x08, 0x95, 0x02, 0x81, 0x06, 0xc0, 0xc0,
+        ];
+        let mut text = String::new();
+        walk_rdesc_items(&bytes, |_, description, indent| {
+            text += &format!("{:indent$}{description}\n", "");
+        })
+        .unwrap();
+        assert_eq!(compile(&text).unwrap(), bytes.to_vec());
+    }
+
+    #[test]
+    fn test_compile_items() {
+        let mut compiler = Compiler::new();
+        let mut compile = |s: &str| compiler.compile_item(s).unwrap();
+        assert_eq!(compile("Usage Page (Digitizers)"), vec![0x05, 0x0d]);
+        assert_eq!(compile("Usage (Contact Count Maximum)"), vec![0x09, 0x55]);
+        // Not in the Digitizers page
+        assert_eq!(
+            compile("Usage (AC Pan)"),
+            vec![0x0b, 0x38, 0x02, 0x0c, 0x00]
+        );
+        assert_eq!(
+            compile("Usage Page (Vendor Defined Page FF00)"),
+            vec![0x06, 0x00, 0xff]
+        );
+        assert_eq!(compile("Usage (Vendor Usage 0x01)"), vec![0x09, 0x01]);
+        assert_eq!(compile("Logical Maximum (255)"), vec![0x26, 0xff, 0x00]);
+        assert_eq!(
+            compile("Logical Maximum (4294967295)"),
+            vec![0x27, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(compile("Logical Minimum (-127)"), vec![0x15, 0x81]);
+        assert_eq!(compile("Unit (SILinear: cm)"), vec![0x65, 0x11]);
+        assert_eq!(compile("Unit (SILinear: g·cm²s⁻²)"), vec![0x66, 0x21, 0xe1]);
+        assert_eq!(compile("Unit Exponent (-2)"), vec![0x55, 0x0e]);
+        assert_eq!(compile("Input (Cnst,Var,Abs)"), vec![0x81, 0x03]);
+        assert_eq!(
+            compile("Feature (Data,Var,Abs,Buff)"),
+            vec![0xb2, 0x02, 0x01]
+        );
+        assert_eq!(compile("Collection (Application)"), vec![0xa1, 0x01]);
+        assert_eq!(compile("End Collection"), vec![0xc0]);
+
+        assert!(Compiler::new()
+            .compile_item("Usage (No Such Usage)")
+            .is_err());
+        assert!(Compiler::new().compile_item("Report Size 8").is_err());
+    }
+
+    #[test]
+    fn test_item_text() {
+        assert_eq!(
+            item_text(
+                "# 🮥 0x05, 0x01,                    // Usage Page (Generic Desktop)        0"
+            ),
+            Some("Usage Page (Generic Desktop)")
+        );
+        assert_eq!(
+            item_text("    0x81, 0x02,   //   Input (Data,Var,Abs)"),
+            Some("Input (Data,Var,Abs)")
+        );
+        assert_eq!(
+            item_text("  Report Count (2) // two axes"),
+            Some("Report Count (2)")
+        );
+        assert_eq!(item_text("# just a comment"), None);
+        assert_eq!(item_text("R: 2 c0 c0"), None);
+        assert_eq!(
+            item_text("#   Bytes                          // Field Name                              Offset"),
+            None
+        );
+    }
+}
@@ -0,0 +1,366 @@
+// SPDX-License-Identifier: MIT
+//
+// Compare two report descriptors at the level of their parsed reports
+// and fields rather than their bytes.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::{get_hut_str, json, Backend, Outfile, OutputFormat, Styles};
+use hidreport::*;
+
+/// A single difference between two report descriptors, the string
+/// describes the report and field (if any)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    Added(String),
+    Removed(String),
+    Changed(String),
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::Added(s) => write!(f, "+ {s}"),
+            Difference::Removed(s) => write!(f, "- {s}"),
+            Difference::Changed(s) => write!(f, "~ {s}"),
+        }
+    }
+}
+
+/// The properties of a [Field] we compare
+struct FieldSummary {
+    /// The usage (or usage range) that identifies this field
+    name: String,
+    bits: Range<usize>,
+    logical_range: Option<(i32, i32)>,
+    physical_range: Option<(i32, i32)>,
+    unit: Option<u32>,
+    unit_exponent: Option<i8>,
+    collections: String,
+}
+
+fn collections_to_str(collections: &[Collection]) -> String {
+    collections
+        .iter()
+        .map(|c| match c.usages().first() {
+            Some(usage) => format!("{:?} ({})", c.collection_type(), get_hut_str(usage)),
+            None => format!("{:?}", c.collection_type()),
+        })
+        .collect::<Vec<String>>()
+        .join(" > ")
+}
+
+fn range_to_str(range: &Option<(i32, i32)>) -> String {
+    match range {
+        Some((min, max)) => format!("{min}..={max}"),
+        None => "none".to_string(),
+    }
+}
+
+fn bits_to_str(bits: &Range<usize>) -> String {
+    format!("{}..={}", bits.start, bits.end - 1)
+}
+
+impl From<&Field> for FieldSummary {
+    fn from(field: &Field) -> FieldSummary {
+        let collections = collections_to_str(field.collections());
+        match field {
+            Field::Constant(c) => FieldSummary {
+                name: "padding".to_string(),
+                bits: c.bits.clone(),
+                logical_range: None,
+                physical_range: None,
+                unit: None,
+                unit_exponent: None,
+                collections,
+            },
+            Field::Variable(v) => FieldSummary {
+                name: get_hut_str(&v.usage),
+                bits: v.bits.clone(),
+                logical_range: Some((i32::from(v.logical_minimum), i32::from(v.logical_maximum))),
+                physical_range: v
+                    .physical_minimum
+                    .zip(v.physical_maximum)
+                    .map(|(min, max)| (i32::from(min), i32::from(max))),
+                unit: v.unit.map(u32::from),
+                unit_exponent: v.unit_exponent.map(|e| e.exponent()),
+                collections,
+            },
+            Field::Array(a) => FieldSummary {
+                name: match (a.usages().first(), a.usages().last()) {
+                    (Some(first), Some(last)) if a.usages().len() > 1 => {
+                        format!("array {}..={}", get_hut_str(first), get_hut_str(last))
+                    }
+                    (Some(first), _) => format!("array {}", get_hut_str(first)),
+                    _ => "array".to_string(),
+                },
+                bits: a.bits.clone(),
+                logical_range: Some((i32::from(a.logical_minimum), i32::from(a.logical_maximum))),
+                physical_range: a
+                    .physical_minimum
+                    .zip(a.physical_maximum)
+                    .map(|(min, max)| (i32::from(min), i32::from(max))),
+                unit: a.unit.map(u32::from),
+                unit_exponent: a.unit_exponent.map(|e| e.exponent()),
+                collections,
+            },
+        }
+    }
+}
+
+/// The properties of a [Report] we compare
+struct ReportSummary {
+    name: String,
+    size_in_bits: usize,
+    fields: Vec<FieldSummary>,
+}
+
+impl ReportSummary {
+    fn new(direction: &str, report: &impl Report) -> ReportSummary {
+        let name = match report.report_id() {
+            Some(id) => format!("{direction} report {id}"),
+            None => format!("{direction} report"),
+        };
+        ReportSummary {
+            name,
+            size_in_bits: report.size_in_bits(),
+            fields: report.fields().iter().map(FieldSummary::from).collect(),
+        }
+    }
+
+    /// The fields keyed by their name and the number of fields with the
+    /// same name before them, so the second "Button 1" is matched with the
+    /// second "Button 1" in the other report.
+    fn keyed_fields(&self) -> Vec<((&str, usize), &FieldSummary)> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        self.fields
+            .iter()
+            .map(|f| {
+                let count = counts.entry(&f.name).or_default();
+                *count += 1;
+                ((f.name.as_str(), *count), f)
+            })
+            .collect()
+    }
+
+    /// All distinct collection paths of this report, in order
+    fn collections(&self) -> Vec<&str> {
+        let mut collections: Vec<&str> = Vec::new();
+        for f in self.fields.iter() {
+            if !collections.contains(&f.collections.as_str()) {
+                collections.push(&f.collections);
+            }
+        }
+        collections
+    }
+}
+
+fn summarize(rdesc: &ReportDescriptor) -> Vec<ReportSummary> {
+    rdesc
+        .input_reports()
+        .iter()
+        .map(|r| ReportSummary::new("input", r))
+        .chain(
+            rdesc
+                .output_reports()
+                .iter()
+                .map(|r| ReportSummary::new("output", r)),
+        )
+        .chain(
+            rdesc
+                .feature_reports()
+                .iter()
+                .map(|r| ReportSummary::new("feature", r)),
+        )
+        .collect()
+}
+
+fn diff_fields(report: &str, old: &FieldSummary, new: &FieldSummary) -> Vec<Difference> {
+    let name = format!("{report}: {}", old.name);
+    let mut changes = Vec::new();
+    if old.bits != new.bits {
+        changes.push(format!(
+            "bits {} → {}",
+            bits_to_str(&old.bits),
+            bits_to_str(&new.bits)
+        ));
+    }
+    if old.logical_range != new.logical_range {
+        changes.push(format!(
+            "logical range {} → {}",
+            range_to_str(&old.logical_range),
+            range_to_str(&new.logical_range)
+        ));
+    }
+    if old.physical_range != new.physical_range {
+        changes.push(format!(
+            "physical range {} → {}",
+            range_to_str(&old.physical_range),
+            range_to_str(&new.physical_range)
+        ));
+    }
+    if old.unit != new.unit {
+        let unit = |u: Option<u32>| u.map_or("none".to_string(), |u| format!("{u:#x}"));
+        changes.push(format!("unit {} → {}", unit(old.unit), unit(new.unit)));
+    }
+    if old.unit_exponent != new.unit_exponent {
+        let exp = |e: Option<i8>| e.map_or("none".to_string(), |e| format!("{e}"));
+        changes.push(format!(
+            "unit exponent {} → {}",
+            exp(old.unit_exponent),
+            exp(new.unit_exponent)
+        ));
+    }
+    if old.collections != new.collections {
+        changes.push(format!(
+            "collection {} → {}",
+            old.collections, new.collections
+        ));
+    }
+    changes
+        .into_iter()
+        .map(|c| Difference::Changed(format!("{name}: {c}")))
+        .collect()
+}
+
+fn diff_reports(old: &ReportSummary, new: &ReportSummary) -> Vec<Difference> {
+    let mut diffs = Vec::new();
+    let report = &old.name;
+    if old.size_in_bits != new.size_in_bits {
+        diffs.push(Difference::Changed(format!(
+            "{report}: size {} → {} bits",
+            old.size_in_bits, new.size_in_bits
+        )));
+    }
+
+    let old_collections = old.collections();
+    let new_collections = new.collections();
+    for c in old_collections.iter() {
+        if !new_collections.contains(c) {
+            diffs.push(Difference::Removed(format!("{report}: collection {c}")));
+        }
+    }
+    for c in new_collections.iter() {
+        if !old_collections.contains(c) {
+            diffs.push(Difference::Added(format!("{report}: collection {c}")));
+        }
+    }
+
+    let old_fields = old.keyed_fields();
+    let new_fields = new.keyed_fields();
+    for (key, f) in old_fields.iter() {
+        match new_fields.iter().find(|(k, _)| k == key) {
+            Some((_, newf)) => diffs.extend(diff_fields(report, f, newf)),
+            None => diffs.push(Difference::Removed(format!(
+                "{report}: {} at bits {}",
+                f.name,
+                bits_to_str(&f.bits)
+            ))),
+        }
+    }
+    for (key, f) in new_fields.iter() {
+        if !old_fields.iter().any(|(k, _)| k == key) {
+            diffs.push(Difference::Added(format!(
+                "{report}: {} at bits {}",
+                f.name,
+                bits_to_str(&f.bits)
+            )));
+        }
+    }
+
+    diffs
+}
+
+/// Compare two report descriptors and return their differences, an empty
+/// vector if they describe the same reports.
+pub fn diff(old: &ReportDescriptor, new: &ReportDescriptor) -> Vec<Difference> {
+    let old_reports = summarize(old);
+    let new_reports = summarize(new);
+    let mut diffs = Vec::new();
+
+    for r in old_reports.iter() {
+        match new_reports.iter().find(|n| n.name == r.name) {
+            Some(n) => diffs.extend(diff_reports(r, n)),
+            None => diffs.push(Difference::Removed(r.name.clone())),
+        }
+    }
+    for r in new_reports.iter() {
+        if !old_reports.iter().any(|o| o.name == r.name) {
+            diffs.push(Difference::Added(r.name.clone()));
+        }
+    }
+
+    diffs
+}
+
+/// Compare the report descriptors of two backends and print the
+/// differences.
+pub fn diff_backends(old: &impl Backend, new: &impl Backend) -> Result<()> {
+    let old_rdesc = ReportDescriptor::try_from(old.rdesc())?;
+    let new_rdesc = ReportDescriptor::try_from(new.rdesc())?;
+    let diffs = diff(&old_rdesc, &new_rdesc);
+
+    if Outfile::output_format() == OutputFormat::Json {
+        for d in diffs.iter() {
+            json::write_difference(d);
+        }
+        return Ok(());
+    }
+
+    Outfile::new().write_comment(&format!("--- {}", old.name()));
+    Outfile::new().write_comment(&format!("+++ {}", new.name()));
+    if diffs.is_empty() {
+        Outfile::new().write_comment("The report descriptors describe the same reports");
+    }
+    for d in diffs.iter() {
+        let style = match d {
+            Difference::Added(_) => Styles::InputItem,
+            Difference::Removed(_) => Styles::Note,
+            Difference::Changed(_) => Styles::OutputItem,
+        };
+        Outfile::new().writeln(&style, &format!("{d}"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        // 3 buttons, 5 bits padding, 8 bit X/Y
+        let old = [
+            0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01,
+            0x29, 0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01,
+            0x75, 0x05, 0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f,
+            0x75, 0x08, 0x95, 0x02, 0x81, 0x06, 0xc0, 0xc0,
+        ];
+        let rdesc = ReportDescriptor::try_from(old.as_slice()).unwrap();
+        assert!(diff(&rdesc, &rdesc).is_empty());
+
+        // 5 buttons instead of 3, 3 bits padding, X/Y with 16 bits each
+        // but the same -127..=127 range
+        let mut bytes = old;
+        bytes[15] = 0x05; // Usage Maximum (5)
+        bytes[21] = 0x05; // Report Count (5)
+        bytes[29] = 0x03; // Report Size (3)
+        bytes[43] = 0x10; // Report Size (16)
+        let new = ReportDescriptor::try_from(bytes.as_slice()).unwrap();
+
+        let diffs = diff(&rdesc, &new);
+        assert_eq!(
+            diffs,
+            vec![
+                Difference::Changed("input report: size 24 → 40 bits".into()),
+                Difference::Changed("input report: padding: bits 3..=7 → 5..=7".into()),
+                Difference::Changed("input report: X: bits 8..=15 → 8..=23".into()),
+                Difference::Changed("input report: Y: bits 16..=23 → 24..=39".into()),
+                Difference::Added("input report: Button 4 at bits 3..=3".into()),
+                Difference::Added("input report: Button 5 at bits 4..=4".into()),
+            ]
+        );
+    }
+}
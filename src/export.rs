@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: MIT
+//
+// Export a report descriptor as source code to paste into a kernel driver
+// or a device firmware.
+
+use clap::ValueEnum;
+use hidreport::hid::Item;
+use std::fmt::Write;
+
+use crate::walk_rdesc_items;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// A C array with one item per line, e.g. for a kernel driver
+    C,
+    /// A C array using the TinyUSB HID_* macros
+    Tinyusb,
+}
+
+fn bytes_to_str(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("0x{b:02x},"))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// The TinyUSB macro name for an item's type and tag and whether it
+/// has a `_N` variant for data sizes other than one byte. Report ID is
+/// missing here because TinyUSB's `HID_REPORT_ID()` includes the trailing
+/// comma.
+fn tinyusb_macro(item_type: u8, tag: u8) -> Option<(&'static str, bool)> {
+    let name = match (item_type, tag) {
+        (0, 0x8) => ("HID_INPUT", false),
+        (0, 0x9) => ("HID_OUTPUT", false),
+        (0, 0xa) => ("HID_COLLECTION", false),
+        (0, 0xb) => ("HID_FEATURE", false),
+        (1, 0x0) => ("HID_USAGE_PAGE", true),
+        (1, 0x1) => ("HID_LOGICAL_MIN", true),
+        (1, 0x2) => ("HID_LOGICAL_MAX", true),
+        (1, 0x3) => ("HID_PHYSICAL_MIN", true),
+        (1, 0x4) => ("HID_PHYSICAL_MAX", true),
+        (1, 0x5) => ("HID_UNIT_EXPONENT", true),
+        (1, 0x6) => ("HID_UNIT", true),
+        (1, 0x7) => ("HID_REPORT_SIZE", true),
+        (1, 0x9) => ("HID_REPORT_COUNT", true),
+        (2, 0x0) => ("HID_USAGE", true),
+        (2, 0x1) => ("HID_USAGE_MIN", true),
+        (2, 0x2) => ("HID_USAGE_MAX", true),
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// The TinyUSB macro invocation for the item's bytes, falling back to
+/// `HID_REPORT_ITEM()` for items without a dedicated macro and to the
+/// plain bytes for long items.
+fn tinyusb_item(bytes: &[u8]) -> String {
+    let prefix = bytes[0];
+    let (tag, item_type, size) = (prefix >> 4, (prefix >> 2) & 0x3, prefix & 0x3);
+    if prefix == 0xfe || item_type == 3 {
+        return bytes_to_str(bytes);
+    }
+    let value = bytes[1..]
+        .iter()
+        .rev()
+        .fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+    let value = format!("0x{value:0width$x}", width = (bytes.len() - 1).max(1) * 2);
+
+    match (item_type, tag, size) {
+        (0, 0xc, 0) => "HID_COLLECTION_END,".into(),
+        (1, 0xa, 0) => "HID_PUSH,".into(),
+        (1, 0xb, 0) => "HID_POP,".into(),
+        _ => match tinyusb_macro(item_type, tag) {
+            Some((name, _)) if size == 1 => format!("{name}({value}),"),
+            Some((name, true)) => format!("{name}_N({value}, {size}),"),
+            _ => {
+                let item_type =
+                    ["RI_TYPE_MAIN", "RI_TYPE_GLOBAL", "RI_TYPE_LOCAL"][usize::from(item_type)];
+                format!("HID_REPORT_ITEM({value}, {tag}, {item_type}, {size}),")
+            }
+        },
+    }
+}
+
+/// The body of the C array, one item per line with its description as
+/// comment and indented by collection. Bytes that do not parse as report
+/// descriptor are printed without comments.
+pub(crate) fn c_array_items(bytes: &[u8], format: ExportFormat) -> String {
+    let mut s = String::new();
+    let result = walk_rdesc_items(bytes, |rdesc_item, description, indent| {
+        let item = rdesc_item.item();
+        let code = match format {
+            ExportFormat::C => bytes_to_str(item.bytes()),
+            ExportFormat::Tinyusb => tinyusb_item(item.bytes()),
+        };
+        let code = format!("{:indent$}{code}", "");
+        let _ = writeln!(s, "\t{code:40} // {description}");
+    });
+    if result.is_err() {
+        s.clear();
+        for chunk in bytes.chunks(8) {
+            let _ = writeln!(s, "\t{}", bytes_to_str(chunk));
+        }
+    }
+    s
+}
+
+/// Export the report descriptor as C array named `name`
+pub fn export(bytes: &[u8], format: ExportFormat, name: &str) -> String {
+    let declaration = match format {
+        ExportFormat::C => format!("static const __u8 {name}[] = {{"),
+        ExportFormat::Tinyusb => format!("uint8_t const {name}[] = {{"),
+    };
+    format!(
+        "/* Report descriptor length: {} bytes */\n{declaration}\n{}}};\n",
+        bytes.len(),
+        c_array_items(bytes, format)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export() {
+        // Two nested collections, so the items are indented up to 4 spaces
+        let bytes = [
+            0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01,
+            0x29, 0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01,
+            0x75, 0x05, 0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f,
+            0x75, 0x08, 0x95, 0x02, 0x81, 0x06, 0xc0, 0xc0,
+        ];
+        let c = export(&bytes, ExportFormat::C, "rdesc");
+        let lines: Vec<&str> = c.lines().collect();
+        assert_eq!(lines[1], "static const __u8 rdesc[] = {");
+        assert_eq!(
+            lines[2],
+            "\t0x05, 0x01,                              // Usage Page (Generic Desktop)"
+        );
+        assert_eq!(
+            lines[5],
+            "\t  0x09, 0x01,                            // Usage (Pointer)"
+        );
+        assert_eq!(
+            lines[14],
+            "\t    0x81, 0x02,                          // Input (Data,Var,Abs)"
+        );
+        assert_eq!(lines.last(), Some(&"};"));
+
+        let tinyusb = export(&bytes, ExportFormat::Tinyusb, "desc_hid_report");
+        let lines: Vec<&str> = tinyusb.lines().collect();
+        assert_eq!(lines[1], "uint8_t const desc_hid_report[] = {");
+        assert!(lines[2].starts_with("\tHID_USAGE_PAGE(0x01), "));
+        assert!(lines[4].starts_with("\tHID_COLLECTION(0x01), "));
+        assert!(lines[21].starts_with("\t    HID_LOGICAL_MIN(0x81), "));
+        assert!(lines[27].starts_with("\tHID_COLLECTION_END, "));
+
+        assert_eq!(
+            tinyusb_item(&[0x26, 0xff, 0x03]),
+            "HID_LOGICAL_MAX_N(0x03ff, 2),"
+        );
+        assert_eq!(
+            tinyusb_item(&[0x79, 0x04]),
+            "HID_REPORT_ITEM(0x04, 7, RI_TYPE_LOCAL, 1),"
+        );
+        assert_eq!(tinyusb_item(&[0xa4]), "HID_PUSH,");
+        assert_eq!(
+            tinyusb_item(&[0x85, 0x01]),
+            "HID_REPORT_ITEM(0x01, 8, RI_TYPE_GLOBAL, 1),"
+        );
+    }
+}
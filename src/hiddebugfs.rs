@@ -0,0 +1,238 @@
+// SPDX-License-Identifier: MIT
+//
+// Reads captures of the kernel's hid debugfs files. The rdesc file has
+// the report descriptor in hex followed by the kernel's parsed dump:
+//
+// 05 01 09 02 a1 01 09 01 a1 00 05 09 19 01 29 03 ...
+//
+//   INPUT[INPUT]
+//     Field(0)
+//     ...
+//
+// The events file has one line per report, followed by the field values:
+//
+// report (size 3) (unnumbered) =  01 ff 02
+// Button.0001 = 1
+// ...
+
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{Backend, BpfOption, Event, EventNode, ReportDescriptor, ReportDirection, ReportEvent};
+
+pub struct HidDebugfsBackend {
+    name: String,
+    bustype: u32,
+    vid: u32,
+    pid: u32,
+    rdesc: Vec<u8>,
+    events: Vec<Vec<u8>>,
+}
+
+/// Parse the bus, vendor and product ID from the start of a debugfs
+/// directory name, e.g. `0003:046D:C077.0001`
+fn parse_hid_id(name: &str) -> Option<(u32, u32, u32)> {
+    let id = name.get(..14)?;
+    let mut parts = id.split([':', '.']);
+    let mut next = || u32::from_str_radix(parts.next()?, 16).ok();
+    let (bus, vid, pid) = (next()?, next()?, next()?);
+    name.as_bytes().get(14).filter(|c| **c == b'.')?;
+    Some((bus, vid, pid))
+}
+
+/// The reports in an events capture, e.g.
+/// `report (size 3) (unnumbered) =  01 ff 02`
+fn parse_events(data: &str) -> Result<Vec<Vec<u8>>> {
+    data.lines()
+        .filter_map(|line| line.trim().strip_prefix("report (size "))
+        .map(|line| {
+            let (size, rest) = line.split_once(')').context("Invalid report line")?;
+            let size = size.parse::<usize>().context("Invalid report size")?;
+            let (_, bytes) = rest.split_once('=').context("Invalid report line")?;
+            let bytes = hex::decode(bytes.replace(char::is_whitespace, ""))
+                .context("Invalid report bytes")?;
+            if bytes.len() != size {
+                bail!("Invalid report length: {} expected {}", bytes.len(), size);
+            }
+            Ok(bytes)
+        })
+        .collect()
+}
+
+/// Whether this line starts the kernel's dump of a report
+fn is_dump_line(line: &str) -> bool {
+    let l = line.trim();
+    l.starts_with("INPUT[") || l.starts_with("OUTPUT[") || l.starts_with("FEATURE[")
+}
+
+/// Whether `data` looks like a capture of the rdesc file, i.e. has the
+/// kernel's dump after the report descriptor
+pub fn is_rdesc_capture(data: &[u8]) -> bool {
+    String::from_utf8_lossy(data).lines().any(is_dump_line)
+}
+
+impl HidDebugfsBackend {
+    /// Read the rdesc capture in `path` and, if given, the events capture
+    /// in `events`. Reports in the rdesc capture itself, e.g. where both
+    /// files were concatenated, are read as well.
+    pub fn from_paths(path: &Path, events: Option<&Path>) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let mut lines = data.lines().skip_while(|l| l.trim().is_empty());
+        let rdesc = lines.next().context("Missing report descriptor")?;
+        let rdesc = hex::decode(rdesc.replace(char::is_whitespace, ""))
+            .context("Invalid report descriptor")?;
+
+        // Without the kernel's dump following the report descriptor this
+        // is just a number array
+        if !lines.any(is_dump_line) && events.is_none() {
+            bail!("Not a hid debugfs rdesc capture");
+        }
+
+        let mut reports = parse_events(&data)?;
+        if let Some(events) = events {
+            reports.extend(parse_events(&std::fs::read_to_string(events)?)?);
+        }
+
+        let id = [Some(path), events]
+            .into_iter()
+            .flatten()
+            .flat_map(|p| [p.file_name(), p.parent().and_then(|p| p.file_name())])
+            .flatten()
+            .find_map(|name| {
+                let name = name.to_string_lossy();
+                parse_hid_id(&name).map(|ids| (name[..19.min(name.len())].to_string(), ids))
+            });
+        let (name, (bustype, vid, pid)) = id.unwrap_or((String::from("No Name"), (0, 0, 0)));
+
+        Ok(HidDebugfsBackend {
+            name,
+            bustype,
+            vid,
+            pid,
+            rdesc,
+            events: reports,
+        })
+    }
+}
+
+impl TryFrom<&Path> for HidDebugfsBackend {
+    type Error = anyhow::Error;
+
+    fn try_from(path: &Path) -> Result<Self> {
+        HidDebugfsBackend::from_paths(path, None)
+    }
+}
+
+impl Backend for HidDebugfsBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bustype(&self) -> u32 {
+        self.bustype
+    }
+
+    fn vid(&self) -> u32 {
+        self.vid
+    }
+
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn rdesc(&self) -> &[u8] {
+        &self.rdesc
+    }
+
+    fn event_nodes(&self) -> &[EventNode] {
+        &[]
+    }
+
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        rdesc: &ReportDescriptor,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
+        // debugfs has no timestamps
+        for bytes in self.events.iter() {
+            callback(Event::Report(ReportEvent::new(
+                bytes,
+                ReportDirection::Input,
+                rdesc,
+                Duration::ZERO,
+                None,
+            )))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The rdesc file of a mouse: its descriptor bytes, then the kernel's
+    // view of the parsed reports and the input mapping
+    const RDESC: &str = "\
+05 01 09 02 a1 01 09 01 a1 00 05 09 19 01 29 03 15 00 25 01 95 03 75 01 81 02 95 01 75 05 81 01 05 01 09 30 09 31 15 81 25 7f 75 08 95 02 81 06 c0 c0
+
+  INPUT[INPUT]
+    Field(0)
+      Physical(GenericDesktop.Pointer)
+      Application(GenericDesktop.Mouse)
+      Usage(3)
+        Button.0001
+        Button.0002
+        Button.0003
+
+Button.0001 ---> Key.LeftBtn
+";
+
+    const EVENTS: &str = "\
+report (size 3) (unnumbered) =  01 ff 02
+Button.0001 = 1
+Button.0002 = 0
+Button.0003 = 0
+GenericDesktop.X = -1
+GenericDesktop.Y = 2
+
+report (size 3) (unnumbered) =  00 05 00
+Button.0001 = 0
+";
+
+    #[test]
+    fn test_hid_debugfs() {
+        assert_eq!(
+            parse_hid_id("0003:046D:C077.0001"),
+            Some((0x3, 0x46d, 0xc077))
+        );
+        assert_eq!(parse_hid_id("rdesc"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let devdir = dir.path().join("0003:046D:C077.0001");
+        std::fs::create_dir(&devdir).unwrap();
+        let rdesc_path = devdir.join("rdesc");
+        let events_path = devdir.join("events");
+        std::fs::write(&rdesc_path, RDESC).unwrap();
+        std::fs::write(&events_path, EVENTS).unwrap();
+
+        let backend = HidDebugfsBackend::from_paths(&rdesc_path, Some(&events_path)).unwrap();
+        assert_eq!(backend.name(), "0003:046D:C077.0001");
+        assert_eq!((backend.bustype(), backend.vid()), (0x3, 0x046d));
+        assert_eq!(backend.rdesc().len(), 50);
+        assert_eq!(
+            backend.events,
+            vec![vec![0x01, 0xff, 0x02], vec![0x00, 0x05, 0x00]]
+        );
+
+        // A bare hex string is not a debugfs capture
+        assert!(is_rdesc_capture(RDESC.as_bytes()));
+        assert!(!is_rdesc_capture(b"05 01 09 02"));
+        let hex_path = dir.path().join("hex");
+        std::fs::write(&hex_path, "05 01 09 02").unwrap();
+        assert!(HidDebugfsBackend::try_from(hex_path.as_path()).is_err());
+    }
+}
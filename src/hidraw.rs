@@ -2,20 +2,20 @@
 
 use anyhow::{bail, Context, Result};
 use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
-use std::cell::OnceCell;
+use std::cell::{OnceCell, RefCell};
 use std::fs::OpenOptions;
 use std::io::Read;
 use std::os::fd::{AsFd, AsRawFd};
 use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
 use crate::{
-    find_sysfs_path, parse_uevent, print_bpf_input_report_data, print_current_time,
-    print_input_report_data, print_input_report_description, Backend, BpfOption, EventNode,
-    Outfile, ReportDescriptor, Styles,
+    find_sysfs_path, parse_uevent, Backend, BpfEvent, BpfOption, Event, EventNode, Outfile,
+    ReportDescriptor, ReportDirection, ReportEvent, Styles,
 };
 
+use hidreport::Report;
 use libbpf_rs::libbpf_sys;
 use libbpf_rs::skel::OpenSkel as _;
 use libbpf_rs::skel::SkelBuilder as _;
@@ -32,6 +32,13 @@ use hidrecord_tracing::*;
 
 const PACKET_SIZE: usize = 64;
 
+// HID_MAX_BUFFER_SIZE in include/linux/hid.h, the largest report
+// hidraw can give us
+const HID_MAX_BUFFER_SIZE: usize = 16384;
+
+// HIDIOCGFEATURE(len) in linux/hidraw.h
+nix::ioctl_readwrite_buf!(hidiocgfeature, b'H', 0x07, u8);
+
 // A Rust version of hid_recorder_event in hidrecord.bpf.c
 // struct hid_recorder_event {
 // 	__u8 length;
@@ -93,14 +100,57 @@ pub struct HidrawBackend {
     rdesc: Vec<u8>,
     device_path: Option<PathBuf>,
     event_nodes: Vec<EventNode>,
+    feature_reports: OnceCell<Vec<Vec<u8>>>,
 }
 
 impl HidrawBackend {
+    /// Read the current contents of every feature report in the
+    /// report descriptor through HIDIOCGFEATURE.
+    fn read_feature_reports(&self) -> Vec<Vec<u8>> {
+        let Some(path) = &self.device_path else {
+            return Vec::new();
+        };
+        let Ok(rdesc) = ReportDescriptor::try_from(self.rdesc.as_slice()) else {
+            return Vec::new();
+        };
+        let f = match OpenOptions::new().read(true).open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                Outfile::new().write_comment(&format!("Failed to read feature reports: {e}"));
+                return Vec::new();
+            }
+        };
+
+        rdesc
+            .feature_reports()
+            .iter()
+            .filter_map(|r| {
+                // HIDIOCGFEATURE always puts the report number into the first
+                // byte, even where the device doesn't use report IDs
+                let (id, offset) = match r.report_id() {
+                    Some(id) => (u8::from(id), 0),
+                    None => (0, 1),
+                };
+                let mut buf = vec![0u8; r.size_in_bytes() + offset];
+                buf[0] = id;
+                match unsafe { hidiocgfeature(f.as_raw_fd(), &mut buf) } {
+                    Ok(len) => buf.get(offset..len as usize).map(|b| b.to_vec()),
+                    Err(e) => {
+                        Outfile::new()
+                            .write_comment(&format!("Failed to read feature report {id}: {e}"));
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
     fn read_events_loop(
         &self,
         path: &Path,
         rdesc: &ReportDescriptor,
         map_ringbuf: Option<&libbpf_rs::Map>,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
     ) -> Result<()> {
         let mut f = OpenOptions::new()
             .read(true)
@@ -109,15 +159,17 @@ impl HidrawBackend {
 
         let timeout = PollTimeout::try_from(-1).unwrap();
         let start_time: OnceCell<Instant> = OnceCell::new();
-        let mut last_timestamp: Option<Instant> = None;
-        let mut data = [0; 1024];
+        let mut data = vec![0; HID_MAX_BUFFER_SIZE];
         let mut bpf_vec = Vec::new();
+        // The ringbuf callback cannot return errors, so the BPF events
+        // are queued here and passed on after each consume()
+        let bpf_events: RefCell<Vec<Event>> = RefCell::new(Vec::new());
 
         let ringbuf = map_ringbuf.map(|map_ringbuf| {
             let mut builder = libbpf_rs::RingBufferBuilder::new();
             builder
                 .add(map_ringbuf, |data| {
-                    bpf_event_handler(data, &mut bpf_vec, &start_time)
+                    bpf_event_handler(data, &mut bpf_vec, &start_time, &bpf_events)
                 })
                 .unwrap();
             builder.build().unwrap()
@@ -141,18 +193,24 @@ impl HidrawBackend {
 
                 if has_events[0] {
                     match f.read(&mut data) {
-                        Ok(_nbytes) => {
-                            last_timestamp = print_current_time(last_timestamp);
-                            let _ = start_time.get_or_init(|| last_timestamp.unwrap());
-                            print_input_report_description(&data, rdesc)?;
-
-                            let elapsed = start_time.get().unwrap().elapsed();
-                            // This prints the B: 123 00 01 02 ... data line via the callback
+                        Ok(nbytes) => {
+                            let elapsed = start_time.get_or_init(Instant::now).elapsed();
+                            // This collects the matching HID-BPF events, if any
                             if let Some(ref ringbuf) = ringbuf {
                                 let _ = ringbuf.consume();
                             }
+                            for event in bpf_events.take() {
+                                callback(event)?;
+                            }
 
-                            print_input_report_data(&data, rdesc, &elapsed)?;
+                            let event = ReportEvent::new(
+                                &data[..nbytes],
+                                ReportDirection::Input,
+                                rdesc,
+                                elapsed,
+                                Some(SystemTime::now()),
+                            );
+                            callback(Event::Report(event))?;
                         }
                         Err(e) => {
                             if e.kind() != std::io::ErrorKind::WouldBlock {
@@ -163,9 +221,11 @@ impl HidrawBackend {
                 }
                 if *has_events.get(1).unwrap_or(&false) {
                     if let Some(ref ringbuf) = ringbuf {
-                        last_timestamp = print_current_time(last_timestamp);
-                        let _ = start_time.get_or_init(|| last_timestamp.unwrap());
+                        let _ = start_time.get_or_init(Instant::now);
                         let _ = ringbuf.consume();
+                        for event in bpf_events.take() {
+                            callback(event)?;
+                        }
                     }
                 }
             }
@@ -173,6 +233,52 @@ impl HidrawBackend {
     }
 }
 
+/// The hidraw nodes of all interfaces of the USB device that the hidraw
+/// node at `path` belongs to, including `path` itself. A Logitech receiver
+/// for example has one hidraw node per USB interface and one per paired
+/// device.
+pub fn find_sibling_hidraws(path: &Path) -> Result<Vec<PathBuf>> {
+    let Some(hidraw) = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .filter(|name| path.starts_with("/dev") && name.starts_with("hidraw"))
+    else {
+        bail!("Not a hidraw node: {path:?}");
+    };
+    let hidraws = sibling_hidraws(Path::new("/sys/class/hidraw"), &hidraw)?;
+    Ok(hidraws
+        .iter()
+        .map(|name| PathBuf::from("/dev").join(name))
+        .collect())
+}
+
+/// The names of the hidraw devices in the `class` directory (usually
+/// /sys/class/hidraw) on the same USB device as the `hidraw` device
+fn sibling_hidraws(class: &Path, hidraw: &str) -> Result<Vec<String>> {
+    let device = class.join(hidraw).join("device").canonicalize()?;
+    // The HID device is below the USB interface (e.g. 1-9:1.2) with
+    // the USB device (1-9) as parent
+    let usb_device = device
+        .ancestors()
+        .find(|dir| dir.join("bInterfaceNumber").exists())
+        .and_then(|interface| interface.parent())
+        .context(format!("{hidraw} is not a USB device"))?;
+
+    let mut hidraws: Vec<String> = std::fs::read_dir(class)?
+        .flatten()
+        .filter(|entry| {
+            entry
+                .path()
+                .join("device")
+                .canonicalize()
+                .is_ok_and(|device| device.starts_with(usb_device))
+        })
+        .flat_map(|entry| entry.file_name().into_string())
+        .collect();
+    hidraws.sort_by(|a, b| human_sort::compare(a, b));
+    Ok(hidraws)
+}
+
 impl TryFrom<&Path> for HidrawBackend {
     type Error = anyhow::Error;
 
@@ -251,6 +357,7 @@ impl TryFrom<&Path> for HidrawBackend {
                 rdesc: bytes,
                 device_path,
                 event_nodes,
+                feature_reports: OnceCell::new(),
             })
         } else {
             bail!("Not a syfs file or hidraw node");
@@ -283,18 +390,28 @@ impl Backend for HidrawBackend {
         &self.event_nodes
     }
 
-    fn read_events(&self, use_bpf: BpfOption, rdesc: &ReportDescriptor) -> Result<()> {
+    fn feature_reports(&self) -> &[Vec<u8>] {
+        self.feature_reports
+            .get_or_init(|| self.read_feature_reports())
+    }
+
+    fn read_events(
+        &self,
+        use_bpf: BpfOption,
+        rdesc: &ReportDescriptor,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
         if self.device_path.is_none() {
             return Ok(());
         }
         let path = self.device_path.as_ref().unwrap();
         match preload_bpf_tracer(use_bpf, path)? {
-            HidBpfSkel::None => self.read_events_loop(path, rdesc, None)?,
+            HidBpfSkel::None => self.read_events_loop(path, rdesc, None, callback)?,
             HidBpfSkel::StructOps(skel) => {
                 let maps = skel.maps();
                 // We need to keep _link around or the program gets immediately removed
                 let _link = maps.hid_record().attach_struct_ops()?;
-                self.read_events_loop(path, rdesc, Some(maps.events()))?
+                self.read_events_loop(path, rdesc, Some(maps.events()), callback)?
             }
             HidBpfSkel::Tracing(skel, hid_id) => {
                 let attach_args = attach_prog_args {
@@ -305,7 +422,7 @@ impl Backend for HidrawBackend {
 
                 let _link =
                     run_syscall_prog_attach(skel.progs().attach_prog(), attach_args).unwrap();
-                self.read_events_loop(path, rdesc, Some(skel.maps().events()))?
+                self.read_events_loop(path, rdesc, Some(skel.maps().events()), callback)?
             }
         }
         Ok(())
@@ -316,6 +433,7 @@ fn bpf_event_handler(
     data: &[u8],
     buffer: &mut Vec<u8>,
     start_time: &OnceCell<Instant>,
+    events: &RefCell<Vec<Event>>,
 ) -> ::std::os::raw::c_int {
     if data.len() != std::mem::size_of::<hid_recorder_event>() {
         eprintln!(
@@ -347,7 +465,11 @@ fn bpf_event_handler(
     buffer.extend_from_slice(&event.data[..size]);
 
     if event.packet_number == event.packet_count - 1 {
-        print_bpf_input_report_data(buffer, &elapsed);
+        events.borrow_mut().push(Event::Bpf(BpfEvent {
+            elapsed,
+            walltime: Some(SystemTime::now()),
+            bytes: buffer.clone(),
+        }));
     }
     0
 }
@@ -474,3 +596,44 @@ fn run_syscall_prog_attach(
         Ok(args.retval)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sibling_hidraws() {
+        let dir = tempfile::tempdir().unwrap();
+        let usb = dir.path().join("devices/usb1");
+        let receiver = usb.join("1-9");
+        let other = usb.join("1-10");
+        let hid_devices = [
+            ("hidraw0", receiver.join("1-9:1.0/0003:046D:C52B.0001")),
+            ("hidraw1", receiver.join("1-9:1.1/0003:046D:C52B.0002")),
+            // A device paired to the receiver
+            (
+                "hidraw10",
+                receiver.join("1-9:1.2/0003:046D:C52B.0003/0003:046D:4082.0004"),
+            ),
+            ("hidraw2", receiver.join("1-9:1.2/0003:046D:C52B.0003")),
+            ("hidraw3", other.join("1-10:1.0/0003:1234:5678.0005")),
+        ];
+        let class = dir.path().join("class/hidraw");
+        for (hidraw, device) in &hid_devices {
+            std::fs::create_dir_all(device).unwrap();
+            std::fs::create_dir_all(class.join(hidraw)).unwrap();
+            std::os::unix::fs::symlink(device, class.join(hidraw).join("device")).unwrap();
+        }
+        for interface in ["1-9:1.0", "1-9:1.1", "1-9:1.2"] {
+            std::fs::write(receiver.join(interface).join("bInterfaceNumber"), "00").unwrap();
+        }
+        std::fs::write(other.join("1-10:1.0/bInterfaceNumber"), "00").unwrap();
+
+        let expected = vec!["hidraw0", "hidraw1", "hidraw2", "hidraw10"];
+        assert_eq!(sibling_hidraws(&class, "hidraw1").unwrap(), expected);
+        assert_eq!(sibling_hidraws(&class, "hidraw10").unwrap(), expected);
+        assert_eq!(sibling_hidraws(&class, "hidraw3").unwrap(), vec!["hidraw3"]);
+
+        assert!(find_sibling_hidraws(Path::new("/tmp/hidraw0")).is_err());
+    }
+}
@@ -6,8 +6,8 @@ use std::path::Path;
 use std::time::Duration;
 
 use crate::{
-    print_input_report_data, print_input_report_description, Backend, BpfOption, EventNode,
-    ReportDescriptor,
+    Backend, BpfOption, Event, EventNode, Outfile, ReportDescriptor, ReportDirection, ReportEvent,
+    Styles,
 };
 
 // FIXME: add a enum to differ between hid events and bpf events
@@ -23,11 +23,10 @@ pub struct HidRecorderBackend {
     vid: u16,
     pid: u16,
     rdesc: Vec<u8>,
+    feature_reports: Vec<Vec<u8>>,
     events: Vec<HidRecorderEvent>,
 }
 
-impl HidRecorderBackend {}
-
 /// Decode a length-prefixed string of bytes, e.g.
 /// 4 00 01 02 03 04
 /// ^ ^------------^
@@ -48,10 +47,10 @@ fn decode_length_prefixed_data(str: &str) -> Result<(usize, Vec<u8>)> {
     Ok((length, bytes))
 }
 
-impl TryFrom<&Path> for HidRecorderBackend {
-    type Error = anyhow::Error;
-
-    fn try_from(path: &Path) -> Result<Self> {
+impl HidRecorderBackend {
+    /// Read the recording in `path`. If it contains several devices, use
+    /// the one with this `D:` index or the first one if `device` is `None`.
+    pub fn from_path_and_device(path: &Path, device: Option<usize>) -> Result<Self> {
         let f = std::fs::File::open(path)?;
         let lines = std::io::BufReader::new(f)
             .lines()
@@ -63,12 +62,28 @@ impl TryFrom<&Path> for HidRecorderBackend {
         let mut vid: Option<u16> = None;
         let mut pid: Option<u16> = None;
         let mut rdesc: Option<Vec<u8>> = None;
+        let mut feature_reports: Vec<Vec<u8>> = Vec::new();
         let mut events: Vec<HidRecorderEvent> = Vec::new();
+        // The D: indices in a recording of several devices and the
+        // index of the current one
+        let mut devices: Vec<usize> = Vec::new();
+        let mut current = 0;
+        let selected = device.unwrap_or(0);
 
         for line in lines {
             if line.is_empty() || line.starts_with("#") {
                 continue;
             }
+            if let Some(index) = line.strip_prefix("D: ") {
+                current = index.parse::<usize>().context("Invalid device index")?;
+                if !devices.contains(&current) {
+                    devices.push(current);
+                }
+                continue;
+            }
+            if current != selected {
+                continue;
+            }
             match line.split_once(' ') {
                 Some(("N:", rest)) => name = Some(String::from(rest)),
                 Some(("I:", rest)) => {
@@ -87,6 +102,13 @@ impl TryFrom<&Path> for HidRecorderBackend {
                             .1,
                     );
                 }
+                Some(("F:", rest)) => {
+                    feature_reports.push(
+                        decode_length_prefixed_data(rest)
+                            .context("Invalid feature report")?
+                            .1,
+                    );
+                }
                 Some(("E:", rest)) => {
                     let (timestamp, rest) = rest.split_once(' ').context("Missing timestamp")?;
                     let (secs, usecs) = timestamp
@@ -111,14 +133,44 @@ impl TryFrom<&Path> for HidRecorderBackend {
             };
         }
 
-        Ok(HidRecorderBackend {
+        if devices.is_empty() {
+            devices.push(0);
+        }
+        let available = devices
+            .iter()
+            .map(|d| format!("{d}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        if !devices.contains(&selected) {
+            bail!("No device {selected}, available devices: {available}");
+        }
+
+        let backend = HidRecorderBackend {
             name: name.context("Missing name")?,
             bustype: bustype.context("Missing bustype")?,
             vid: vid.context("Missing vid")?,
             pid: pid.context("Missing pid")?,
             rdesc: rdesc.context("Missing rdesc")?,
+            feature_reports,
             events,
-        })
+        };
+        if device.is_none() && devices.len() > 1 {
+            Outfile::new().write_comment_styled(
+                Styles::Note,
+                &format!(
+                    "Using device {selected} of devices {available}, select another one with --device"
+                ),
+            );
+        }
+        Ok(backend)
+    }
+}
+
+impl TryFrom<&Path> for HidRecorderBackend {
+    type Error = anyhow::Error;
+
+    fn try_from(path: &Path) -> Result<Self> {
+        HidRecorderBackend::from_path_and_device(path, None)
     }
 }
 
@@ -147,13 +199,146 @@ impl Backend for HidRecorderBackend {
         &[]
     }
 
-    fn read_events(&self, _use_bpf: BpfOption, rdesc: &ReportDescriptor) -> Result<()> {
+    fn feature_reports(&self) -> &[Vec<u8>] {
+        &self.feature_reports
+    }
+
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        rdesc: &ReportDescriptor,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
         for e in self.events.iter() {
             let elapsed = Duration::from_micros(e.usecs);
-            print_input_report_description(&e.bytes, rdesc)?;
-            print_input_report_data(&e.bytes, rdesc, &elapsed)?;
+            callback(Event::Report(ReportEvent::new(
+                &e.bytes,
+                ReportDirection::Input,
+                rdesc,
+                elapsed,
+                None,
+            )))?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DecodedValue;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    // Mouse: 3 buttons, 5 bits padding, X/Y as signed 8 bit relative values
+    const RECORDING: &str = "\
+# A comment
+N: Test Mouse
+I: 3 1234 5678
+F: 2 00 01
+R: 50 05 01 09 02 a1 01 09 01 a1 00 05 09 19 01 29 03 15 00 25 01 95 03 75 01 81 02 95 01 75 05 81 01 05 01 09 30 09 31 15 81 25 7f 75 08 95 02 81 06 c0 c0
+E: 000000.000000 3 01 ff 02
+E: 000001.000500 3 00 05 00
+";
+
+    #[test]
+    fn test_read_events() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(RECORDING.as_bytes()).unwrap();
+        file.flush().unwrap();
+
+        let backend = HidRecorderBackend::try_from(file.path()).unwrap();
+        assert_eq!(backend.name(), "Test Mouse");
+        assert_eq!(backend.vid(), 0x1234);
+        assert_eq!(backend.feature_reports(), &[vec![0x00, 0x01]]);
+
+        let rdesc = ReportDescriptor::try_from(backend.rdesc()).unwrap();
+        let mut events = Vec::new();
+        backend
+            .read_events(BpfOption::Never, &rdesc, &mut |e| {
+                events.push(e);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        let Event::Report(ref e) = events[1] else {
+            panic!("Expected a report event");
+        };
+        assert_eq!(e.elapsed, Duration::from_micros(1_000_500));
+        assert_eq!(e.bytes, vec![0x00, 0x05, 0x00]);
+        // 3 buttons, padding, x, y
+        let values: Vec<&DecodedValue> = e.fields.iter().map(|f| &f.value).collect();
+        assert_eq!(
+            values,
+            vec![
+                &DecodedValue::Unsigned(0),
+                &DecodedValue::Unsigned(0),
+                &DecodedValue::Unsigned(0),
+                &DecodedValue::Padding,
+                &DecodedValue::Signed(5),
+                &DecodedValue::Signed(0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multiple_devices() {
+        let recording = format!(
+            "D: 0\n{}D: 1\nN: Second Device\nI: 3 1234 9999\nR: 2 05 01\nD: 1\nE: 000000.000100 1 00\nD: 0\nE: 000000.000200 3 00 01 00\n",
+            RECORDING
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(recording.as_bytes()).unwrap();
+        file.flush().unwrap();
+
+        // The first device unless another one is selected
+        let mut backend = None;
+        let output = crate::tests::capture_output(|| {
+            backend = Some(HidRecorderBackend::try_from(file.path()).unwrap());
+        });
+        assert!(output.contains("Using device 0 of devices 0, 1"));
+        let backend = backend.unwrap();
+        assert_eq!(backend.name(), "Test Mouse");
+        assert_eq!(backend.rdesc().len(), 50);
+        assert_eq!(
+            backend.events.iter().map(|e| e.usecs).collect::<Vec<u64>>(),
+            vec![0, 1_000_500, 200]
+        );
+
+        let backend = HidRecorderBackend::from_path_and_device(file.path(), Some(1)).unwrap();
+        assert_eq!(backend.name(), "Second Device");
+        assert_eq!(backend.rdesc(), &[0x05, 0x01]);
+        assert_eq!(
+            backend.events.iter().map(|e| e.usecs).collect::<Vec<u64>>(),
+            vec![100]
+        );
+
+        let missing = HidRecorderBackend::from_path_and_device(file.path(), Some(2));
+        assert!(missing
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("available devices: 0, 1"));
+    }
+
+    #[test]
+    fn test_feature_reports_round_trip() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(RECORDING.as_bytes()).unwrap();
+        file.flush().unwrap();
+        let backend = HidRecorderBackend::try_from(file.path()).unwrap();
+
+        let output = crate::tests::capture_output(|| {
+            crate::process(backend, &crate::Options::default()).unwrap()
+        });
+        assert!(output.lines().any(|l| l == "F: 2 00 01"));
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(output.as_bytes()).unwrap();
+        file.flush().unwrap();
+        let backend = HidRecorderBackend::try_from(file.path()).unwrap();
+        assert_eq!(backend.feature_reports(), &[vec![0x00, 0x01]]);
+    }
+}
@@ -0,0 +1,359 @@
+// SPDX-License-Identifier: MIT
+//
+// JSON Lines output: every line is one self-contained JSON object with
+// a "type" key that identifies the record.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::diff::Difference;
+use crate::lint::Finding;
+use crate::{
+    walk_rdesc_items, Backend, BpfEvent, DecodedField, DecodedValue, Outfile, ReportDirection,
+    ReportEvent,
+};
+use hidreport::hid::{Item, ItemType};
+use hidreport::*;
+
+fn usage_to_json(usage: &Usage) -> Value {
+    let up = u16::from(usage.usage_page);
+    let uid = u16::from(usage.usage_id);
+    let name = hut::Usage::new_from_page_and_id(up, uid)
+        .ok()
+        .map(|u| format!("{u}"));
+    json!({
+        "usage_page": up,
+        "usage_id": uid,
+        "name": name,
+    })
+}
+
+fn item_type_to_str(item_type: &ItemType) -> &'static str {
+    match item_type {
+        ItemType::Main(..) => "main",
+        ItemType::Global(..) => "global",
+        ItemType::Local(..) => "local",
+        ItemType::Long => "long",
+        ItemType::Reserved => "reserved",
+    }
+}
+
+fn field_to_json(field: &Field) -> Value {
+    let bits = field.bits();
+    match field {
+        Field::Constant(_) => json!({
+            "type": "constant",
+            "bit_offset": bits.start,
+            "bit_size": bits.len(),
+        }),
+        Field::Variable(v) => json!({
+            "type": "variable",
+            "bit_offset": bits.start,
+            "bit_size": bits.len(),
+            "usage": usage_to_json(&v.usage),
+            "logical_minimum": i32::from(v.logical_minimum),
+            "logical_maximum": i32::from(v.logical_maximum),
+            "physical_minimum": v.physical_minimum.map(i32::from),
+            "physical_maximum": v.physical_maximum.map(i32::from),
+            "unit": v.unit.map(u32::from),
+            "unit_exponent": v.unit_exponent.map(|e| e.exponent()),
+        }),
+        Field::Array(a) => json!({
+            "type": "array",
+            "bit_offset": bits.start,
+            "bit_size": bits.len(),
+            "report_count": usize::from(a.report_count),
+            "usages": a.usages().iter().map(usage_to_json).collect::<Vec<Value>>(),
+            "logical_minimum": i32::from(a.logical_minimum),
+            "logical_maximum": i32::from(a.logical_maximum),
+            "physical_minimum": a.physical_minimum.map(i32::from),
+            "physical_maximum": a.physical_maximum.map(i32::from),
+            "unit": a.unit.map(u32::from),
+            "unit_exponent": a.unit_exponent.map(|e| e.exponent()),
+        }),
+    }
+}
+
+fn report_to_json(direction: &str, report: &impl Report) -> Value {
+    json!({
+        "type": "report",
+        "direction": direction,
+        "report_id": report.report_id().as_ref().map(u8::from),
+        "size_in_bits": report.size_in_bits(),
+        "fields": report.fields().iter().map(field_to_json).collect::<Vec<Value>>(),
+    })
+}
+
+/// Returns the decoded value(s) of this field or `None` for padding
+/// and for the fields of a string that are part of a preceding field
+fn field_values_to_json(field: &DecodedField) -> Option<Value> {
+    let value = match &field.value {
+        DecodedValue::Padding | DecodedValue::TextContinued => return None,
+        DecodedValue::Unsigned(v) => json!(v),
+        DecodedValue::Signed(v) => json!(v),
+        // Serialized as strings, JSON numbers are not precise beyond 53 bits
+        DecodedValue::BigUnsigned(v) => json!(v.to_string()),
+        DecodedValue::BigSigned(v) => json!(v.to_string()),
+        DecodedValue::Text(text) => json!(text),
+        DecodedValue::Bytes(bytes) => json!(hex::encode(bytes)),
+        DecodedValue::Array(values) => {
+            return Some(json!({
+                "type": "array",
+                "bit_offset": field.bits.start,
+                "values": values.iter().map(|(v, _)| *v).collect::<Vec<u32>>(),
+                "usages": values
+                    .iter()
+                    .map(|(_, u)| u.as_ref().map(usage_to_json))
+                    .collect::<Vec<Option<Value>>>(),
+            }))
+        }
+    };
+    Some(json!({
+        "type": "variable",
+        "bit_offset": field.bits.start,
+        "usage": field.usage.as_ref().map(usage_to_json),
+        "value": value,
+    }))
+}
+
+/// Add the index of the device to the record, for recordings of
+/// several devices
+fn with_device(mut record: Value, device: Option<usize>) -> Value {
+    if let Some(device) = device {
+        record["device"] = json!(device);
+    }
+    record
+}
+
+/// Write the device, its report descriptor, the given feature reports
+/// and the parsed items and reports as JSON records. `device` is the
+/// index of the device in recordings of several devices, every record
+/// carries it.
+pub fn write_description(
+    backend: &impl Backend,
+    feature_reports: &[Vec<u8>],
+    device: Option<usize>,
+) -> Result<ReportDescriptor> {
+    let bytes = backend.rdesc();
+    let write = |record: Value| Outfile::new().write_json(&with_device(record, device));
+
+    write(json!({
+        "type": "device",
+        "name": backend.name(),
+        "bustype": backend.bustype(),
+        "vendor_id": backend.vid(),
+        "product_id": backend.pid(),
+    }));
+    write(json!({
+        "type": "report_descriptor",
+        "length": bytes.len(),
+        "bytes": bytes,
+    }));
+
+    let rdesc = ReportDescriptor::try_from(bytes)?;
+    for f in feature_reports {
+        let event = ReportEvent::new(f, ReportDirection::Feature, &rdesc, Duration::ZERO, None);
+        let fields = event.is_matched().then(|| {
+            event
+                .fields
+                .iter()
+                .filter_map(field_values_to_json)
+                .collect::<Vec<Value>>()
+        });
+        write(json!({
+            "type": "feature_report",
+            "length": f.len(),
+            "bytes": f,
+            "fields": fields,
+        }));
+    }
+
+    walk_rdesc_items(bytes, |rdesc_item, description, indent| {
+        let item = rdesc_item.item();
+        write(json!({
+            "type": "item",
+            "offset": rdesc_item.offset(),
+            "bytes": item.bytes(),
+            "item_type": item_type_to_str(&item.item_type()),
+            "depth": indent / 2,
+            "description": description,
+        }));
+    })?;
+
+    for r in rdesc.input_reports() {
+        write(report_to_json("input", r));
+    }
+    for r in rdesc.output_reports() {
+        write(report_to_json("output", r));
+    }
+    for r in rdesc.feature_reports() {
+        write(report_to_json("feature", r));
+    }
+
+    for node in backend.event_nodes() {
+        write(json!({
+            "type": "event_node",
+            "path": node.path(),
+            "name": node.name(),
+        }));
+    }
+
+    Ok(rdesc)
+}
+
+/// The JSON record of a single report, including the decoded values
+/// of each field.
+fn event_to_json(event: &ReportEvent, device: Option<usize>) -> Value {
+    let fields: Vec<Value> = event
+        .fields
+        .iter()
+        .filter_map(field_values_to_json)
+        .collect();
+
+    with_device(
+        json!({
+            "type": "event",
+            "direction": event.direction.to_string(),
+            "timestamp_us": event.elapsed.as_micros() as u64,
+            "report_id": event.report_id.as_ref().map(u8::from),
+            "length": event.bytes.len(),
+            "bytes": event.bytes,
+            "matched": event.is_matched(),
+            "expected_length": event.report_size,
+            "fields": fields,
+        }),
+        device,
+    )
+}
+
+/// Write a single report as JSON record. `device` is the index of the
+/// device in recordings of several devices.
+pub fn write_event(event: &ReportEvent, device: Option<usize>) {
+    Outfile::new().write_json(&event_to_json(event, device));
+}
+
+/// Write the data captured through HID-BPF as JSON record.
+pub fn write_bpf_event(event: &BpfEvent, device: Option<usize>) {
+    Outfile::new().write_json(&with_device(
+        json!({
+            "type": "bpf_event",
+            "timestamp_us": event.elapsed.as_micros() as u64,
+            "length": event.bytes.len(),
+            "bytes": event.bytes,
+        }),
+        device,
+    ));
+}
+
+/// Write a problem found in the report descriptor as JSON record.
+pub fn write_lint_finding(finding: &Finding) {
+    Outfile::new().write_json(&json!({
+        "type": "lint",
+        "severity": finding.severity.to_string(),
+        "offset": finding.offset,
+        "message": finding.message,
+    }));
+}
+
+/// Write a difference between two report descriptors as JSON record.
+pub fn write_difference(difference: &Difference) {
+    let (change, description) = match difference {
+        Difference::Added(s) => ("added", s),
+        Difference::Removed(s) => ("removed", s),
+        Difference::Changed(s) => ("changed", s),
+    };
+    Outfile::new().write_json(&json!({
+        "type": "diff",
+        "change": change,
+        "description": description,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::BinaryBackend;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    // One unnumbered 24 bit input report: buttons 1-3, 5 bits of padding,
+    // then X and Y from -127 to 127
+    const MOUSE: [u8; 50] = [
+        0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29,
+        0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05,
+        0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f, 0x75, 0x08, 0x95,
+        0x02, 0x81, 0x06, 0xc0, 0xc0,
+    ];
+
+    #[test]
+    fn test_report_to_json() {
+        let rdesc = ReportDescriptor::try_from(MOUSE.as_slice()).unwrap();
+        let report = rdesc.input_reports().first().unwrap();
+        let v = report_to_json("input", report);
+        assert_eq!(v["type"], "report");
+        assert_eq!(v["report_id"], Value::Null);
+        assert_eq!(v["size_in_bits"], 24);
+        let fields = v["fields"].as_array().unwrap();
+        // 3 buttons, 1 padding, x, y
+        assert_eq!(fields.len(), 6);
+        assert_eq!(fields[3]["type"], "constant");
+        assert_eq!(fields[4]["usage"]["name"], "X");
+        assert_eq!(fields[4]["logical_minimum"], -127);
+    }
+
+    #[test]
+    fn test_field_values_to_json() {
+        let rdesc = ReportDescriptor::try_from(MOUSE.as_slice()).unwrap();
+        let bytes = [0b101, 0xff, 0x02];
+        let event = ReportEvent::new(&bytes, ReportDirection::Input, &rdesc, Duration::ZERO, None);
+        let values: Vec<Value> = event
+            .fields
+            .iter()
+            .filter_map(field_values_to_json)
+            .collect();
+        assert_eq!(values.len(), 5);
+        assert_eq!(values[0]["value"], 1);
+        assert_eq!(values[1]["value"], 0);
+        assert_eq!(values[2]["value"], 1);
+        assert_eq!(values[3]["value"], -1);
+        assert_eq!(values[4]["value"], 2);
+        assert_eq!(values[4]["usage"]["usage_id"], 0x31);
+    }
+
+    #[test]
+    fn test_event_to_json() {
+        let rdesc = ReportDescriptor::try_from(MOUSE.as_slice()).unwrap();
+        let bytes = [0b101, 0xff, 0x02];
+        let event = ReportEvent::new(&bytes, ReportDirection::Input, &rdesc, Duration::ZERO, None);
+        let v = event_to_json(&event, None);
+        assert_eq!(v["type"], "event");
+        assert!(v.get("device").is_none());
+        let v = event_to_json(&event, Some(1));
+        assert_eq!(v["device"], 1);
+    }
+
+    #[test]
+    fn test_write_description() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&MOUSE).unwrap();
+        file.flush().unwrap();
+        let backend = BinaryBackend::try_from(file.path()).unwrap();
+
+        let output = crate::tests::capture_output(|| {
+            for device in 0..2 {
+                write_description(&backend, &[vec![0x00, 0x01, 0x00]], Some(device)).unwrap();
+            }
+        });
+        let records: Vec<Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        // device, report_descriptor, feature_report, 26 items, 1 report
+        assert_eq!(records.len(), 2 * 30);
+        for (device, records) in records.chunks(30).enumerate() {
+            assert_eq!(records[0]["type"], "device");
+            assert!(records.iter().all(|r| r["device"] == device));
+        }
+    }
+}
@@ -0,0 +1,2530 @@
+// SPDX-License-Identifier: MIT
+//
+//! The backends and decoders behind the `hid-recorder` utility.
+//!
+//! A [Backend] provides a device's name, ids and report descriptor and
+//! reads the device's events, either live from a hidraw device or from one
+//! of the supported recording formats:
+//!
+//! ```no_run
+//! use hid_recorder::hidrecording::HidRecorderBackend;
+//! use hid_recorder::hidreport::ReportDescriptor;
+//! use hid_recorder::{Backend, BpfOption, DecodedValue, Event};
+//! use std::path::Path;
+//!
+//! let backend = HidRecorderBackend::try_from(Path::new("mouse.hid")).unwrap();
+//! let rdesc = ReportDescriptor::try_from(backend.rdesc()).unwrap();
+//! backend
+//!     .read_events(BpfOption::Never, &rdesc, &mut |event| {
+//!         if let Event::Report(report) = event {
+//!             for field in report.fields.iter() {
+//!                 if let DecodedValue::Signed(v) = field.value {
+//!                     println!("{:?}: {v}", field.usage);
+//!                 }
+//!             }
+//!         }
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! ```
+
+use anyhow::{bail, Context, Result};
+use clap::{ColorChoice, ValueEnum};
+use owo_colors::{OwoColorize, Rgb, Stream::Stdout, Style};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+// we reuse ColorChoice for your `--bpf` argument
+pub use clap::ColorChoice as BpfOption;
+// our public API uses the hidreport types
+pub use hidreport;
+
+use hidreport::hid::{
+    CollectionItem, GlobalItem, Item, ItemType, LocalItem, MainDataItem, MainItem,
+    ReportDescriptorItem, ReportDescriptorItems,
+};
+use hidreport::*;
+
+static mut OUTFILE: OnceLock<
+    std::sync::Mutex<std::cell::RefCell<std::io::LineWriter<std::fs::File>>>,
+> = OnceLock::new();
+
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    /// The output of the current test, see `tests::capture_output`
+    static CAPTURED_OUTPUT: std::cell::RefCell<Option<Vec<u8>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+pub enum Prefix {
+    Name,
+    Id,
+    ReportDescriptor,
+    Event,
+    Bpf,
+    Feature,
+    Device,
+}
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Prefix::Name => "N",
+            Prefix::Id => "I",
+            Prefix::ReportDescriptor => "R",
+            Prefix::Event => "E",
+            Prefix::Bpf => "B",
+            Prefix::Feature => "F",
+            Prefix::Device => "D",
+        };
+        write!(f, "{s}:")
+    }
+}
+
+pub enum Outfile {
+    Stdout,
+    File(&'static mut std::sync::Mutex<std::cell::RefCell<std::io::LineWriter<std::fs::File>>>),
+    #[cfg(test)]
+    Captured,
+}
+
+impl Default for Outfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Outfile {
+    pub fn new() -> Self {
+        #[cfg(test)]
+        if CAPTURED_OUTPUT.with(|c| c.borrow().is_some()) {
+            return Outfile::Captured;
+        }
+        unsafe {
+            #[allow(static_mut_refs)]
+            match OUTFILE.get_mut() {
+                None => Outfile::Stdout,
+                Some(o) => Outfile::File(o),
+            }
+        }
+    }
+
+    /// The output format selected on the commandline, [OutputFormat::Text]
+    /// unless changed.
+    pub fn output_format() -> OutputFormat {
+        *OUTPUT_FORMAT.get().unwrap_or(&OutputFormat::Text)
+    }
+
+    pub fn init(color: ColorChoice, output_file: &str, output_format: OutputFormat) -> Result<()> {
+        // Bit lame but easier to just set the env for owo_colors to figure out the rest
+        unsafe {
+            match color {
+                ColorChoice::Never => std::env::set_var("NO_COLOR", "1"),
+                ColorChoice::Auto => {}
+                ColorChoice::Always => std::env::set_var("FORCE_COLOR", "1"),
+            }
+        }
+        if output_file != "-" {
+            let out = std::fs::File::create(output_file).unwrap();
+            let _ = unsafe {
+                #[allow(static_mut_refs)]
+                OUTFILE.set(std::sync::Mutex::new(std::cell::RefCell::new(
+                    std::io::LineWriter::new(out),
+                )))
+            };
+        }
+        let _ = OUTPUT_FORMAT.set(output_format);
+        Ok(())
+    }
+
+    // In JSON mode every line must be a JSON object so all the
+    // human-readable output is quietly dropped here.
+    fn write(&mut self, style: &Styles, msg: &str) {
+        if Outfile::output_format() == OutputFormat::Json {
+            return;
+        }
+        write!(
+            self,
+            "{}",
+            msg.if_supports_color(Stdout, |text| text.style(style.into()))
+        )
+        .unwrap();
+    }
+
+    fn writeln(&mut self, style: &Styles, msg: &str) {
+        if Outfile::output_format() == OutputFormat::Json {
+            return;
+        }
+        writeln!(
+            self,
+            "{}",
+            msg.if_supports_color(Stdout, |text| text.style(style.into()))
+        )
+        .unwrap();
+    }
+
+    /// Write a generic unstyled comment
+    pub fn write_comment(&mut self, msg: &str) {
+        self.writeln(&Styles::None, format!("# {msg}").as_ref());
+    }
+
+    /// Write a generic comment with styling
+    pub fn write_comment_styled(&mut self, style: Styles, msg: &str) {
+        self.writeln(&style, format!("# {msg}").as_ref());
+    }
+
+    /// Write the item information as a comment (typically at the top of the file)
+    pub fn write_item_comment(
+        &mut self,
+        item_type: ItemType,
+        item: &str,
+        bytes: &[u8],
+        indent: usize,
+        offset: usize,
+    ) {
+        let bytes = bytes
+            .iter()
+            .map(|b| format!("0x{b:02x}, "))
+            .collect::<Vec<String>>()
+            .join("");
+
+        let style = match item_type {
+            ItemType::Main(MainItem::Input(..)) => Styles::InputItem,
+            ItemType::Main(MainItem::Output(..)) => Styles::OutputItem,
+            ItemType::Main(MainItem::Feature(..)) => Styles::FeatureItem,
+            ItemType::Global(GlobalItem::ReportId { .. }) => Styles::ReportId,
+            ItemType::Global(GlobalItem::UsagePage { .. }) => Styles::UsagePage,
+            ItemType::Local(LocalItem::Usage { .. }) => Styles::Usage,
+            ItemType::Local(LocalItem::UsageId { .. }) => Styles::Usage,
+            ItemType::Local(LocalItem::UsageMinimum { .. }) => Styles::Usage,
+            ItemType::Local(LocalItem::UsageMaximum { .. }) => Styles::Usage,
+            _ => Styles::None,
+        };
+
+        let indented = format!("{:indent$}{}", "", item);
+        let prefix = style.as_str();
+        self.writeln(
+            &style,
+            format!("# {prefix} {bytes:30} // {indented:41} {offset}").as_ref(),
+        );
+    }
+
+    /// Print a separator line for logical separation between sections
+    pub fn separator(&mut self) {
+        self.writeln(
+            &Styles::Separator,
+            "##############################################################################",
+        );
+    }
+
+    /// Write the (colored) prefix for the given report, if any
+    pub fn report_comment_prefix(&mut self, report_id: &Option<ReportId>) {
+        let report_style = if let Some(report_id) = report_id {
+            Styles::Report {
+                report_id: *report_id,
+            }
+        } else {
+            Styles::None
+        };
+        self.write(&Styles::None, "# ");
+        self.write(&report_style, report_style.as_str());
+        self.write(&Styles::None, " ");
+    }
+
+    /// Print a comment related to some report, prefixed with a colored
+    /// version of the report id
+    pub fn report_comment(&mut self, report_id: &Option<ReportId>, msg: &str) {
+        self.report_comment_prefix(report_id);
+        self.writeln(&Styles::None, msg);
+    }
+
+    /// Print a comment related to some report, the comment message contains
+    /// of several individually styled components
+    pub fn report_comment_components(
+        &mut self,
+        report_id: &Option<ReportId>,
+        components: &[(Styles, String)],
+    ) {
+        self.report_comment_prefix(report_id);
+        for (style, msg) in components {
+            Outfile::new().write(style, msg.to_string().as_ref());
+        }
+        self.writeln(&Styles::None, "");
+    }
+
+    /// Write an actual data entry (unlike a comment)
+    pub fn write_data(&mut self, prefix: Prefix, datastr: &str) {
+        self.writeln(&Styles::Data, format!("{prefix} {datastr}").as_str());
+    }
+
+    /// Write an actual data entry that consists of several individually
+    /// styled components
+    pub fn write_data_components(&mut self, prefix: Prefix, components: &[(Styles, String)]) {
+        self.write(&Styles::Data, format!("{prefix} ").as_str());
+        for (style, msg) in components {
+            self.write(style, msg);
+        }
+        self.writeln(&Styles::None, "");
+    }
+
+    /// Write the index of the device the following lines belong to, for
+    /// recordings of several devices
+    pub fn write_device_index(&mut self, index: usize) {
+        self.write_data(Prefix::Device, index.to_string().as_str());
+    }
+
+    pub fn write_name(&mut self, name: &str) {
+        self.write_data(Prefix::Name, name.to_string().as_str());
+    }
+    pub fn write_id(&mut self, bustype: u32, vid: u32, pid: u32) {
+        self.write_data(Prefix::Id, format!("{bustype:x} {vid:x} {pid:x}").as_str());
+    }
+
+    pub fn write_report_descriptor(&mut self, bytes: &[u8]) {
+        let bytestr = bytes
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<String>>()
+            .join(" ");
+        self.write_data(
+            Prefix::ReportDescriptor,
+            format!("{} {bytestr}", bytes.len()).as_str(),
+        );
+    }
+
+    pub fn write_feature_report(&mut self, bytes: &[u8]) {
+        let bytestr = bytes
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<String>>()
+            .join(" ");
+        self.write_data(
+            Prefix::Feature,
+            format!("{} {bytestr}", bytes.len()).as_str(),
+        );
+    }
+
+    /// Write a single JSON record on its own line
+    pub fn write_json(&mut self, record: &serde_json::Value) {
+        writeln!(self, "{record}").unwrap();
+    }
+
+    /// Write a timestamp comment
+    pub fn write_timestamp(&mut self) {
+        self.writeln(
+            &Styles::Timestamp,
+            format!(
+                "# Current time: {}",
+                chrono::prelude::Local::now().format("%H:%M:%S")
+            )
+            .as_ref(),
+        )
+    }
+}
+
+impl Write for Outfile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Outfile::Stdout => std::io::stdout().write(buf),
+            Outfile::File(o) => o.get_mut().unwrap().deref().borrow_mut().write(buf),
+            #[cfg(test)]
+            Outfile::Captured => {
+                CAPTURED_OUTPUT.with(|c| c.borrow_mut().as_mut().unwrap().write(buf))
+            }
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Outfile::Stdout => std::io::stdout().flush(),
+            Outfile::File(o) => o.get_mut().unwrap().deref().borrow_mut().flush(),
+            #[cfg(test)]
+            Outfile::Captured => Ok(()),
+        }
+    }
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            Outfile::Stdout => std::io::stdout().write_all(buf),
+            Outfile::File(o) => o.get_mut().unwrap().deref().borrow_mut().write_all(buf),
+            #[cfg(test)]
+            Outfile::Captured => {
+                CAPTURED_OUTPUT.with(|c| c.borrow_mut().as_mut().unwrap().write_all(buf))
+            }
+        }
+    }
+    fn write_fmt(&mut self, args: std::fmt::Arguments<'_>) -> std::io::Result<()> {
+        match self {
+            Outfile::Stdout => std::io::stdout().write_fmt(args),
+            Outfile::File(o) => o.get_mut().unwrap().deref().borrow_mut().write_fmt(args),
+            #[cfg(test)]
+            Outfile::Captured => {
+                CAPTURED_OUTPUT.with(|c| c.borrow_mut().as_mut().unwrap().write_fmt(args))
+            }
+        }
+    }
+}
+
+pub struct EventNode {
+    name: String,
+    path: PathBuf,
+}
+
+impl EventNode {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+pub trait Backend {
+    fn name(&self) -> &str;
+    fn bustype(&self) -> u32;
+    fn vid(&self) -> u32;
+    fn pid(&self) -> u32;
+    fn rdesc(&self) -> &[u8];
+    fn event_nodes(&self) -> &[EventNode];
+    /// The contents of the device's feature reports, if known. Each entry
+    /// starts with the report ID if the report descriptor uses report IDs.
+    fn feature_reports(&self) -> &[Vec<u8>] {
+        &[]
+    }
+    /// Read the events from this backend and pass each one to `callback`.
+    /// For a live device this only returns on error.
+    fn read_events(
+        &self,
+        use_bpf: BpfOption,
+        rdesc: &ReportDescriptor,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()>;
+}
+
+impl<B: Backend + ?Sized> Backend for Box<B> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+    fn bustype(&self) -> u32 {
+        (**self).bustype()
+    }
+    fn vid(&self) -> u32 {
+        (**self).vid()
+    }
+    fn pid(&self) -> u32 {
+        (**self).pid()
+    }
+    fn rdesc(&self) -> &[u8] {
+        (**self).rdesc()
+    }
+    fn event_nodes(&self) -> &[EventNode] {
+        (**self).event_nodes()
+    }
+    fn feature_reports(&self) -> &[Vec<u8>] {
+        (**self).feature_reports()
+    }
+    fn read_events(
+        &self,
+        use_bpf: BpfOption,
+        rdesc: &ReportDescriptor,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
+        (**self).read_events(use_bpf, rdesc, callback)
+    }
+}
+
+/// A single event as read by [Backend::read_events]
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A HID report from the device or recording
+    Report(ReportEvent),
+    /// A HID report as seen by HID-BPF, before the kernel processes it
+    Bpf(BpfEvent),
+}
+
+/// The direction of a HID report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReportDirection {
+    Input,
+    Output,
+    Feature,
+}
+
+impl std::fmt::Display for ReportDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ReportDirection::Input => "input",
+            ReportDirection::Output => "output",
+            ReportDirection::Feature => "feature",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Find the report in the given direction that matches these bytes,
+/// by report ID where the report descriptor uses report IDs.
+pub fn find_report<'a>(
+    rdesc: &'a ReportDescriptor,
+    direction: ReportDirection,
+    bytes: &[u8],
+) -> Option<&'a dyn Report> {
+    fn find<'a>(reports: &'a [impl Report], bytes: &[u8]) -> Option<&'a dyn Report> {
+        let first = reports.first()?;
+        // Without report IDs there is only one report
+        if first.report_id().is_none() {
+            return Some(first);
+        }
+        let id = bytes.first()?;
+        reports
+            .iter()
+            .find(|r| r.report_id().as_ref().map(u8::from) == Some(*id))
+            .map(|r| r as &dyn Report)
+    }
+
+    match direction {
+        ReportDirection::Input => find(rdesc.input_reports(), bytes),
+        ReportDirection::Output => find(rdesc.output_reports(), bytes),
+        ReportDirection::Feature => find(rdesc.feature_reports(), bytes),
+    }
+}
+
+/// A HID report and its decoded values
+#[derive(Debug, Clone)]
+pub struct ReportEvent {
+    /// Whether this is an input, output or feature report
+    pub direction: ReportDirection,
+    /// The time elapsed since the first event
+    pub elapsed: Duration,
+    /// The wall-clock time for events read from a device, `None` for recordings
+    pub walltime: Option<SystemTime>,
+    /// The report ID, if the report descriptor uses report IDs
+    pub report_id: Option<ReportId>,
+    /// The size in bytes of the matching report in the report descriptor,
+    /// `None` if the bytes do not match any report. The fields are only
+    /// decoded for a matching report. Note that this may differ from the
+    /// length of the bytes if the device sends short or long reports.
+    pub report_size: Option<usize>,
+    /// The bytes of this report exactly as received, including the
+    /// report ID (if any)
+    pub bytes: Vec<u8>,
+    /// The decoded fields, in the same order as the [Report::fields]
+    pub fields: Vec<DecodedField>,
+}
+
+impl ReportEvent {
+    /// Find the report in the given direction matching the bytes and
+    /// decode all its fields. Where no report matches, the event
+    /// contains the bytes only.
+    ///
+    /// The bytes are kept as-is even if their length differs from the
+    /// report's size in the report descriptor. Like the kernel, a short
+    /// report is decoded as if padded with zeroes.
+    pub fn new(
+        bytes: &[u8],
+        direction: ReportDirection,
+        rdesc: &ReportDescriptor,
+        elapsed: Duration,
+        walltime: Option<SystemTime>,
+    ) -> ReportEvent {
+        let Some(report) = find_report(rdesc, direction, bytes) else {
+            return ReportEvent {
+                direction,
+                elapsed,
+                walltime,
+                report_id: None,
+                report_size: None,
+                bytes: bytes.to_vec(),
+                fields: Vec::new(),
+            };
+        };
+        let mut padded = bytes.to_vec();
+        padded.resize(report.size_in_bytes().max(bytes.len()), 0);
+        let mut fields: Vec<DecodedField> = report
+            .fields()
+            .iter()
+            .map(|f| DecodedField::new(&padded, f))
+            .collect();
+        join_text_fields(&padded, &mut fields);
+
+        ReportEvent {
+            direction,
+            elapsed,
+            walltime,
+            report_id: *report.report_id(),
+            report_size: Some(report.size_in_bytes()),
+            bytes: bytes.to_vec(),
+            fields,
+        }
+    }
+
+    /// `true` if these bytes match a report in the report descriptor
+    pub fn is_matched(&self) -> bool {
+        self.report_size.is_some()
+    }
+
+    /// `true` if these bytes match a report in the report descriptor
+    /// but their length differs from that report's size
+    pub fn is_length_mismatch(&self) -> bool {
+        self.report_size
+            .is_some_and(|size| size != self.bytes.len())
+    }
+}
+
+/// The data of a HID report as captured through HID-BPF
+#[derive(Debug, Clone)]
+pub struct BpfEvent {
+    /// The time elapsed since the first event
+    pub elapsed: Duration,
+    /// The wall-clock time this event was captured
+    pub walltime: Option<SystemTime>,
+    /// The bytes as seen by the HID-BPF program
+    pub bytes: Vec<u8>,
+}
+
+/// The value(s) extracted from a single [Field]
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedValue {
+    /// Constant padding bits
+    Padding,
+    Unsigned(u32),
+    Signed(i32),
+    /// The value of a field between 33 and 128 bits wide
+    BigUnsigned(u128),
+    /// The value of a signed field between 33 and 128 bits wide
+    BigSigned(i128),
+    /// The bits of a field wider than 128 bits, least significant byte first
+    Bytes(Vec<u8>),
+    /// The string in a field whose usage is a text property. Strings in
+    /// several fields with the same usage, e.g. one field per UTF-16 code
+    /// unit, are joined into the first of these fields.
+    Text(String),
+    /// A field whose bits are part of the [DecodedValue::Text] of a
+    /// preceding field
+    TextContinued,
+    /// The values of an array field, each with its usage if the
+    /// value maps to one
+    Array(Vec<(u32, Option<Usage>)>),
+}
+
+/// Extract the given bits of the report into a little-endian byte vector,
+/// i.e. the first bit in the range is the least significant bit of the
+/// first byte. The bits do not need to be aligned to byte boundaries.
+fn extract_bits(bytes: &[u8], bits: &Range<usize>) -> Vec<u8> {
+    let mut data = vec![0u8; (bits.len() + 7) / 8];
+    for (idx, bit) in bits.clone().enumerate() {
+        let byte = bytes.get(bit / 8).copied().unwrap_or(0);
+        if (byte >> (bit % 8)) & 0x1 != 0 {
+            data[idx / 8] |= 1 << (idx % 8);
+        }
+    }
+    data
+}
+
+enum TextEncoding {
+    Utf8,
+    Utf16,
+}
+
+/// The usages whose values are strings rather than numbers
+fn text_encoding(usage: &Usage) -> Option<TextEncoding> {
+    let up = u16::from(usage.usage_page);
+    let uid = u16::from(usage.usage_id);
+    match (up, uid) {
+        // Auxiliary Display / Display Data
+        (0x14, 0x2c) => Some(TextEncoding::Utf8),
+        // Sensors / Property: Friendly Name, Persistent Unique ID,
+        // Sensor Manufacturer, Model, Serial Number and Description
+        (0x20, 0x301 | 0x302 | 0x305..=0x308) => Some(TextEncoding::Utf16),
+        _ => None,
+    }
+}
+
+fn decode_text(data: &[u8], encoding: TextEncoding) -> Option<String> {
+    let text = match encoding {
+        TextEncoding::Utf8 => String::from_utf8(data.to_vec()).ok()?,
+        TextEncoding::Utf16 => {
+            let units: Vec<u16> = data
+                .chunks(2)
+                .map(|c| u16::from_le_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+                .collect();
+            String::from_utf16(&units).ok()?
+        }
+    };
+    // Strings are zero-padded to the size of the field
+    Some(text.trim_end_matches('\0').to_string())
+}
+
+/// Decode a variable field wider than 32 bits
+fn decode_wide_value(
+    bytes: &[u8],
+    bits: &Range<usize>,
+    usage: &Usage,
+    is_signed: bool,
+) -> DecodedValue {
+    let data = extract_bits(bytes, bits);
+    if let Some(text) = text_encoding(usage).and_then(|enc| decode_text(&data, enc)) {
+        return DecodedValue::Text(text);
+    }
+    if bits.len() > 128 {
+        return DecodedValue::Bytes(data);
+    }
+
+    let mut buf = [0u8; 16];
+    buf[..data.len()].copy_from_slice(&data);
+    let value = u128::from_le_bytes(buf);
+    if is_signed {
+        // sign-extend from the field's width to 128 bits
+        let shift = 128 - bits.len();
+        DecodedValue::BigSigned(((value << shift) as i128) >> shift)
+    } else {
+        DecodedValue::BigUnsigned(value)
+    }
+}
+
+/// Join each run of consecutive narrow variable fields with the same text
+/// usage into a single string, e.g. a sensor's Friendly Name declared
+/// as Report Size 16 and Report Count 32. The string is the value of the
+/// first field of the run, the others are [DecodedValue::TextContinued].
+fn join_text_fields(bytes: &[u8], fields: &mut [DecodedField]) {
+    let is_number =
+        |f: &DecodedField| matches!(f.value, DecodedValue::Unsigned(_) | DecodedValue::Signed(_));
+    let mut start = 0;
+    while start < fields.len() {
+        let Some(usage) = fields[start]
+            .usage
+            .filter(|u| is_number(&fields[start]) && text_encoding(u).is_some())
+        else {
+            start += 1;
+            continue;
+        };
+        let end = fields[start..]
+            .iter()
+            .position(|f| f.usage != Some(usage) || !is_number(f))
+            .map_or(fields.len(), |len| start + len);
+        let bits = fields[start].bits.start..fields[end - 1].bits.end;
+        if let Some(text) =
+            text_encoding(&usage).and_then(|enc| decode_text(&extract_bits(bytes, &bits), enc))
+        {
+            fields[start].value = DecodedValue::Text(text);
+            for f in fields[start + 1..end].iter_mut() {
+                f.value = DecodedValue::TextContinued;
+            }
+        }
+        start = end;
+    }
+}
+
+/// A [Field] of a report with the value(s) extracted from that report
+#[derive(Debug, Clone)]
+pub struct DecodedField {
+    /// The bit range of this field within the report
+    pub bits: Range<usize>,
+    /// The usage of a variable field. For array fields this is only
+    /// set if the array has a single usage, otherwise see the
+    /// per-value usages in [DecodedValue::Array].
+    pub usage: Option<Usage>,
+    pub value: DecodedValue,
+}
+
+impl DecodedField {
+    pub fn new(bytes: &[u8], field: &Field) -> DecodedField {
+        match field {
+            Field::Constant(_) => DecodedField {
+                bits: field.bits().clone(),
+                usage: None,
+                value: DecodedValue::Padding,
+            },
+            Field::Variable(var) => {
+                let value = if var.bits.len() <= 32 {
+                    if var.is_signed() {
+                        DecodedValue::Signed(var.extract(bytes).unwrap().into())
+                    } else {
+                        DecodedValue::Unsigned(var.extract(bytes).unwrap().into())
+                    }
+                } else {
+                    decode_wide_value(bytes, &var.bits, &var.usage, var.is_signed())
+                };
+                DecodedField {
+                    bits: var.bits.clone(),
+                    usage: Some(var.usage),
+                    value,
+                }
+            }
+            Field::Array(arr) => {
+                // The values in the array are usage values between usage min/max
+                let vs: Vec<u32> = arr.extract(bytes).unwrap().iter().map(u32::from).collect();
+                if arr.usages().len() > 1 {
+                    let usage_range = arr.usage_range();
+                    let values = vs
+                        .into_iter()
+                        .map(|v| {
+                            // Does the value have a usage page?
+                            let usage = if (v & 0xffff0000) != 0 {
+                                Usage::from(v)
+                            } else {
+                                Usage::from_page_and_id(
+                                    usage_range.minimum().usage_page(),
+                                    UsageId::from(v as u16),
+                                )
+                            };
+                            // Usage within range?
+                            (v, usage_range.lookup_usage(&usage).copied())
+                        })
+                        .collect();
+                    DecodedField {
+                        bits: arr.bits.clone(),
+                        usage: None,
+                        value: DecodedValue::Array(values),
+                    }
+                } else {
+                    DecodedField {
+                        bits: arr.bits.clone(),
+                        usage: arr.usages().first().copied(),
+                        value: DecodedValue::Array(vs.into_iter().map(|v| (v, None)).collect()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub enum Styles {
+    #[default]
+    None,
+    InputItem,
+    OutputItem,
+    FeatureItem,
+    ReportId,
+    Data,
+    Separator,
+    Timestamp,
+    Note,
+    Changed,
+    Report {
+        report_id: ReportId,
+    },
+    Bpf,
+    Usage,
+    UsagePage,
+    EventNodes,
+}
+
+impl From<&Styles> for Style {
+    fn from(styles: &Styles) -> Style {
+        match styles {
+            Styles::None => Style::new(),
+            Styles::Bpf => Style::new().blue(),
+            Styles::Data => Style::new().red(),
+            Styles::Note => Style::new().red().bold(),
+            Styles::Changed => Style::new().red().bold().reversed(),
+            Styles::InputItem => Style::new().green().bold(),
+            Styles::OutputItem => Style::new().yellow().bold(),
+            Styles::FeatureItem => Style::new().blue().bold(),
+            Styles::ReportId => Style::new().magenta().bold(),
+            Styles::Separator => Style::new().magenta(),
+            Styles::Timestamp => Style::new().purple(),
+            Styles::Report { report_id } => Style::new().on_color(match u8::from(report_id) % 7 {
+                1 => Rgb(0xfc, 0x8d, 0x62),
+                2 => Rgb(0x8d, 0xa0, 0xcb),
+                3 => Rgb(0xe7, 0x8a, 0xc3),
+                4 => Rgb(0xa6, 0xd8, 0x54),
+                5 => Rgb(0xff, 0xd9, 0x2f),
+                6 => Rgb(0xe5, 0xc4, 0x94),
+                _ => Rgb(0x66, 0xc2, 0xa5),
+            }),
+            Styles::Usage => Style::new().bold(),
+            Styles::UsagePage => Style::new().bold(),
+            Styles::EventNodes => Style::new(),
+        }
+    }
+}
+
+impl Styles {
+    fn as_str(&self) -> &str {
+        match self {
+            Styles::None => " ",
+            Styles::Bpf => "",
+            Styles::Data => "",
+            Styles::Note => " ",
+            Styles::Changed => "",
+            Styles::InputItem => "┇",
+            Styles::OutputItem => "┊",
+            Styles::FeatureItem => "║",
+            Styles::ReportId => "┅",
+            Styles::Separator => "",
+            Styles::Timestamp => "",
+            Styles::Report { report_id } => match u8::from(report_id) % 7 {
+                1 => "░",
+                2 => "▒",
+                3 => "▓",
+                4 => "▚",
+                5 => "▞",
+                6 => "▃",
+                _ => "▘",
+            },
+            Styles::Usage => "🭬",
+            Styles::UsagePage => "🮥",
+            Styles::EventNodes => " ",
+        }
+    }
+}
+
+const MAX_USAGES_DISPLAYED: usize = 5;
+
+pub mod binary;
+pub mod bluez;
+pub mod bpffixup;
+pub mod compile;
+pub mod diff;
+pub mod export;
+pub mod hiddebugfs;
+pub mod hidraw;
+pub mod hidrecording;
+mod json;
+pub mod libinput;
+pub mod lint;
+pub mod lsusb;
+pub mod numberarray;
+pub mod pcapng;
+pub mod replay;
+pub mod uhid;
+pub mod usbhiddump;
+pub mod usbmon;
+pub mod wireshark;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// The hid-recorder format with human-readable comments
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Default)]
+pub struct Options {
+    pub full: bool,
+    /// Only describe the device, without reading its feature reports
+    /// or waiting for events
+    pub only_describe: bool,
+    pub bpf: ColorChoice,
+    /// Print variable field values converted to their physical unit
+    pub physical_units: bool,
+    /// Only print the fields that changed since the previous report
+    /// with the same report ID
+    pub changes_only: bool,
+}
+
+fn fmt_main_item(item: &MainItem) -> String {
+    let (prefix, details) = match item {
+        MainItem::Input(i) => {
+            let details = vec![
+                if i.is_constant() { "Cnst" } else { "Data" },
+                if i.is_variable() { "Var" } else { "Arr" },
+                if i.is_relative() { "Rel" } else { "Abs" },
+                if i.wraps() { ",Wrap" } else { "" },
+                if i.is_nonlinear() { ",NonLin" } else { "" },
+                if i.has_no_preferred_state() {
+                    "NoPref"
+                } else {
+                    ""
+                },
+                if i.has_null_state() { "Null" } else { "" },
+                if i.is_buffered_bytes() { "Buff" } else { "" },
+            ];
+            ("Input", details)
+        }
+        MainItem::Output(i) => {
+            let details = vec![
+                if i.is_constant() { "Cnst" } else { "Data" },
+                if i.is_variable() { "Var" } else { "Arr" },
+                if i.is_relative() { "Rel" } else { "Abs" },
+                if i.wraps() { ",Wrap" } else { "" },
+                if i.is_nonlinear() { ",NonLin" } else { "" },
+                if i.has_no_preferred_state() {
+                    "NoPref"
+                } else {
+                    ""
+                },
+                if i.has_null_state() { "Null" } else { "" },
+                if i.is_volatile() { "Vol" } else { "" },
+                if i.is_buffered_bytes() { "Buff" } else { "" },
+            ];
+            ("Output", details)
+        }
+        MainItem::Feature(i) => {
+            let details = vec![
+                if i.is_constant() { "Cnst" } else { "Data" },
+                if i.is_variable() { "Var" } else { "Arr" },
+                if i.is_relative() { "Rel" } else { "Abs" },
+                if i.wraps() { "Wrap" } else { "" },
+                if i.is_nonlinear() { "NonLin" } else { "" },
+                if i.has_no_preferred_state() {
+                    "NoPref"
+                } else {
+                    ""
+                },
+                if i.has_null_state() { "Null" } else { "" },
+                if i.is_volatile() { "Vol" } else { "" },
+                if i.is_buffered_bytes() { "Buff" } else { "" },
+            ];
+            ("Feature", details)
+        }
+        MainItem::Collection(c) => {
+            let details = vec![match c {
+                CollectionItem::Physical => "Physical",
+                CollectionItem::Application => "Application",
+                CollectionItem::Logical => "Logical",
+                CollectionItem::Report => "Report",
+                CollectionItem::NamedArray => "NamedArray",
+                CollectionItem::UsageSwitch => "UsageSwitch",
+                CollectionItem::UsageModifier => "UsageModifier",
+                CollectionItem::Reserved { .. } => "Reserved",
+                CollectionItem::VendorDefined { .. } => "VendorDefined",
+            }];
+            ("Collection", details)
+        }
+        MainItem::EndCollection => return String::from("End Collection"),
+    };
+
+    let details = details
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect::<Vec<String>>()
+        .join(",");
+    format!("{prefix} ({details})")
+}
+
+fn fmt_global_item(item: &GlobalItem) -> String {
+    match item {
+        GlobalItem::UsagePage(usage_page) => {
+            let upval = u16::from(usage_page);
+            let up = hut::UsagePage::try_from(upval);
+            let str = match up {
+                Ok(up) => format!("{up}"),
+                Err(_) => format!("Usage Page (0x{upval:04X})"),
+            };
+
+            format!("Usage Page ({str})")
+        }
+        GlobalItem::LogicalMinimum(minimum) => format!("Logical Minimum ({minimum})"),
+        GlobalItem::LogicalMaximum(maximum) => {
+            // Special case -1 as maximum. It's common enough and never means -1 but
+            // we can only know this is we check the minimum for signed-ness.
+            let maximum: i32 = maximum.into();
+            if maximum == -1 {
+                format!("Logical Maximum ({})", maximum as u32)
+            } else {
+                format!("Logical Maximum ({maximum})")
+            }
+        }
+        GlobalItem::PhysicalMinimum(minimum) => format!("Physical Minimum ({minimum})"),
+        GlobalItem::PhysicalMaximum(maximum) => format!("Physical Maximum ({maximum})"),
+        GlobalItem::UnitExponent(exponent) => format!("Unit Exponent ({})", exponent.exponent()),
+        GlobalItem::Unit(unit) => format!(
+            "Unit ({:?}{}{unit})",
+            unit.system(),
+            match unit.system() {
+                UnitSystem::None => "",
+                _ => ": ",
+            }
+        ),
+        GlobalItem::ReportSize(size) => format!("Report Size ({size})"),
+        GlobalItem::ReportId(id) => format!("Report ID ({id})"),
+        GlobalItem::ReportCount(count) => format!("Report Count ({count})"),
+        GlobalItem::Push => "Push".into(),
+        GlobalItem::Pop => "Pop".into(),
+        GlobalItem::Reserved => "Reserved".into(),
+    }
+}
+
+fn fmt_local_item(item: &LocalItem, global_usage_page: &UsagePage) -> String {
+    match item {
+        LocalItem::Usage(usage_page, usage_id) => {
+            let hut = hut::UsagePage::try_from(u16::from(usage_page));
+            let str = match hut {
+                Ok(hut) => {
+                    let uidval = u16::from(usage_id);
+                    let u = hut.to_usage_from_value(uidval);
+                    match u {
+                        Ok(u) => format!("{u}"),
+                        Err(_) => format!("0x{uidval:04X}"),
+                    }
+                }
+                Err(_) => format!("0x{:04x}", u16::from(usage_id)),
+            };
+            format!("Usage ({str})")
+        }
+        LocalItem::UsageId(usage_id) => {
+            let hut = hut::UsagePage::try_from(u16::from(global_usage_page));
+            let str = match hut {
+                Ok(hut) => {
+                    let uidval = u16::from(usage_id);
+                    let u = hut.to_usage_from_value(uidval);
+                    match u {
+                        Ok(u) => format!("{u}"),
+                        Err(_) => format!("0x{uidval:04X}"),
+                    }
+                }
+                Err(_) => format!("0x{:04x}", u16::from(usage_id)),
+            };
+            format!("Usage ({str})")
+        }
+        LocalItem::UsageMinimum(minimum) => format!("Usage Minimum ({minimum})"),
+        LocalItem::UsageMaximum(maximum) => format!("Usage Maximum ({maximum})"),
+        LocalItem::DesignatorIndex(index) => format!("Designator Index ({index})"),
+        LocalItem::DesignatorMinimum(minimum) => format!("Designator Minimum ({minimum})"),
+        LocalItem::DesignatorMaximum(maximum) => format!("Designator Maximum ({maximum})"),
+        LocalItem::StringIndex(index) => format!("String Index ({index})"),
+        LocalItem::StringMinimum(minimum) => format!("String Minimum ({minimum})"),
+        LocalItem::StringMaximum(maximum) => format!("String Maximum ({maximum})"),
+        LocalItem::Delimiter(delimiter) => format!("Delimiter ({delimiter})"),
+        LocalItem::Reserved { value } => format!("Reserved ({value})"),
+    }
+}
+
+fn fmt_item(item: &impl Item, usage_page: &UsagePage) -> String {
+    match item.item_type() {
+        ItemType::Main(mi) => fmt_main_item(&mi),
+        ItemType::Global(gi) => fmt_global_item(&gi),
+        ItemType::Local(li) => fmt_local_item(&li, usage_page),
+        i => format!("{i:?}"),
+    }
+}
+
+/// Walk through the items in the report descriptor and call `func` for each
+/// item with the item's human-readable description and its indentation level.
+fn walk_rdesc_items(
+    bytes: &[u8],
+    mut func: impl FnMut(&ReportDescriptorItem, &str, usize),
+) -> Result<()> {
+    let rdesc_items = ReportDescriptorItems::try_from(bytes)?;
+    let mut indent = 0;
+    let mut usage_pages = vec![UsagePage::from(0u16)]; // Undefined
+
+    for rdesc_item in rdesc_items.iter() {
+        let item = rdesc_item.item();
+        match item.item_type() {
+            ItemType::Main(MainItem::EndCollection) | ItemType::Global(GlobalItem::Pop) => {
+                indent -= 2;
+            }
+            _ => {}
+        }
+        func(
+            rdesc_item,
+            fmt_item(item, usage_pages.first().unwrap()).as_ref(),
+            indent,
+        );
+
+        match item.item_type() {
+            ItemType::Main(MainItem::Collection(_)) => indent += 2,
+            ItemType::Global(GlobalItem::Push) => {
+                let up = usage_pages.first().unwrap();
+                usage_pages.insert(0, *up);
+                indent += 2;
+            }
+            ItemType::Global(GlobalItem::Pop) => {
+                usage_pages.remove(0);
+            }
+            ItemType::Global(GlobalItem::UsagePage(usage_page)) => {
+                usage_pages.remove(0);
+                usage_pages.insert(0, usage_page);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn print_rdesc_items(bytes: &[u8]) -> Result<()> {
+    let header =
+        "#   Bytes                          // Field Name                              Offset";
+    let separator = format!("# {0:-<1$}", "-", header.len() - 2);
+    Outfile::new().writeln(&Styles::None, header);
+    Outfile::new().writeln(&Styles::None, &separator);
+    // Print the device description
+    walk_rdesc_items(bytes, |rdesc_item, description, indent| {
+        let item = rdesc_item.item();
+        Outfile::new().write_item_comment(
+            item.item_type(),
+            description,
+            item.bytes(),
+            indent,
+            rdesc_item.offset(),
+        );
+    })
+}
+
+// This would be easier with udev but let's keep the dependencies relatively minimal.
+pub fn find_sysfs_path(path: &Path) -> Result<PathBuf> {
+    let pathstr = path.to_string_lossy();
+    let sysfs: PathBuf;
+    if pathstr.starts_with("/dev/hidraw") {
+        sysfs = PathBuf::from("/sys/class/hidraw/")
+            .join(path.file_name().unwrap())
+            .join("device");
+    } else if pathstr.starts_with("/dev/input/event") {
+        // /sys/class/input/event0/device/device/hidraw/hidraw4/device/
+        let parent = PathBuf::from("/sys/class/input/")
+            .join(path.file_name().unwrap())
+            .join("device")
+            .join("device")
+            .join("hidraw");
+        if !parent.exists() {
+            bail!("Couldn't find a  hidraw device for this event node, please use /dev/hidraw* instead");
+        }
+        let hidraws: Vec<String> = std::fs::read_dir(&parent)?
+            .flatten()
+            .flat_map(|f| f.file_name().into_string())
+            .filter(|name| name.starts_with("hidraw"))
+            .collect();
+        if hidraws.is_empty() {
+            bail!("Couldn't find a  hidraw device for this event node, please use /dev/hidraw* instead");
+        } else if hidraws.len() > 1 {
+            bail!(
+                "More than one hidraw device for this event node, please use /dev/hidraw* instead"
+            );
+        }
+        sysfs = parent.join(hidraws.first().unwrap()).join("device");
+    } else if path.starts_with("/sys") {
+        let path = path.canonicalize()?;
+        let path = if !path.is_dir() {
+            path.parent().unwrap()
+        } else {
+            &path
+        };
+        // We're now somewhere in one of
+        // in /sys/devices/pci0000:00/0000:00:14.0/usb1/1-9/1-9:1.2/0003:046D:C52B.0003/hidraw/hidraw0
+        // Go up the HID device root
+        sysfs = path
+            .components()
+            .take_while(|c| !c.as_os_str().to_string_lossy().starts_with("hidraw"))
+            .collect();
+    } else {
+        bail!("Don't know how to handle {path:?}");
+    };
+
+    Ok(sysfs)
+}
+
+fn usage_to_str(u: &Usage) -> String {
+    let hutstr: String =
+        match hut::Usage::new_from_page_and_id(u16::from(u.usage_page), u16::from(u.usage_id)) {
+            Err(_) => "<unknown>".into(),
+            Ok(hut::Usage::VendorDefinedPage { vendor_page, usage }) => {
+                format!(
+                    "Vendor Defined Usage {:04x} / {:04x}",
+                    u16::from(vendor_page),
+                    u16::from(&usage)
+                )
+            }
+            Ok(u) => format!("{} / {}", hut::UsagePage::from(&u), u),
+        };
+
+    format!(
+        "{:04x}/{:04x}: {:43}",
+        u16::from(u.usage_page),
+        u16::from(u.usage_id),
+        hutstr
+    )
+}
+
+fn logical_range_to_str(
+    logical_minimum: &LogicalMinimum,
+    logical_maximum: &LogicalMaximum,
+) -> String {
+    let max = match i32::from(logical_maximum) {
+        m @ -1 => format!("0x{m:x}"),
+        m @ 0x7fffffff => format!("0x{m:x}"),
+        m => format!("{m}"),
+    };
+    format!(
+        "Logical Range: {:5}..={:<5}",
+        i32::from(logical_minimum),
+        max
+    )
+}
+
+fn physical_range_to_str(
+    physical_minimum: &Option<PhysicalMinimum>,
+    physical_maximum: &Option<PhysicalMaximum>,
+) -> Option<String> {
+    if let (Some(min), Some(max)) = (physical_minimum, physical_maximum) {
+        Some(format!(
+            "Physical Range: {:5}..={:<5}",
+            i32::from(min),
+            i32::from(max)
+        ))
+    } else {
+        None
+    }
+}
+
+fn unit_to_str(unit: &Option<Unit>) -> Option<String> {
+    if let Some(u) = unit {
+        u.units().map(|units| {
+            format!(
+                "Unit: {:?}{}{}",
+                u.system(),
+                match u.system() {
+                    UnitSystem::None => "",
+                    _ => ": ",
+                },
+                units
+                    .iter()
+                    .map(|u| format!("{u}"))
+                    .collect::<Vec<String>>()
+                    .join("")
+            )
+        })
+    } else {
+        None
+    }
+}
+
+fn bits_to_str(bits: &std::ops::Range<usize>) -> String {
+    if bits.len() > 1 {
+        format!("Bits: {:3}..={:<3}", bits.start, bits.end - 1)
+    } else {
+        format!("Bit:  {:3}      ", bits.start,)
+    }
+}
+
+#[derive(Default)]
+struct PrintableColumn {
+    string: String,
+    style: Styles, // FIXME
+}
+
+impl From<&str> for PrintableColumn {
+    fn from(s: &str) -> PrintableColumn {
+        PrintableColumn {
+            string: s.to_string(),
+            style: Styles::None,
+        }
+    }
+}
+
+impl From<String> for PrintableColumn {
+    fn from(s: String) -> PrintableColumn {
+        PrintableColumn {
+            string: s,
+            style: Styles::None,
+        }
+    }
+}
+
+impl From<Option<String>> for PrintableColumn {
+    fn from(s: Option<String>) -> PrintableColumn {
+        match s {
+            None => PrintableColumn::default(),
+            Some(s) => PrintableColumn {
+                string: s,
+                style: Styles::None,
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct PrintableRow {
+    bits: PrintableColumn,
+    usage: PrintableColumn,
+    logical_range: PrintableColumn,
+    physical_range: PrintableColumn,
+    unit: PrintableColumn,
+}
+
+impl PrintableRow {
+    fn columns(&self) -> impl Iterator<Item = &'_ PrintableColumn> {
+        vec![
+            &self.bits,
+            &self.usage,
+            &self.logical_range,
+            &self.physical_range,
+            &self.unit,
+        ]
+        .into_iter()
+        .filter(move |x| !x.string.is_empty())
+    }
+}
+
+#[derive(Default)]
+struct PrintableTable {
+    rows: Vec<PrintableRow>,
+    colwidths: [usize; 5],
+}
+
+impl PrintableTable {
+    fn add(&mut self, row: PrintableRow) {
+        self.colwidths[0] = std::cmp::max(row.bits.string.len(), self.colwidths[0]);
+        self.colwidths[1] = std::cmp::max(row.usage.string.len(), self.colwidths[1]);
+        self.colwidths[2] = std::cmp::max(row.logical_range.string.len(), self.colwidths[2]);
+        self.colwidths[3] = std::cmp::max(row.physical_range.string.len(), self.colwidths[3]);
+        self.colwidths[4] = std::cmp::max(row.unit.string.len(), self.colwidths[4]);
+        self.rows.push(row);
+    }
+}
+
+fn is_vendor_or_reserved_field(field: &Field) -> bool {
+    match field {
+        Field::Constant(_) => false,
+        Field::Array(_) => false,
+        Field::Variable(v) => {
+            let up: u16 = v.usage.usage_page.into();
+            match hut::UsagePage::try_from(up) {
+                Err(_) => false,
+                Ok(hut::UsagePage::VendorDefinedPage { .. }) => true,
+                Ok(hut::UsagePage::ReservedUsagePage { .. }) => true,
+                Ok(_) => false,
+            }
+        }
+    }
+}
+
+fn vendor_report_filler(count: usize) -> PrintableRow {
+    PrintableRow {
+        bits: PrintableColumn::from("  "),
+        usage: PrintableColumn {
+            string: format!("Total of {count} vendor usages, use --full to see all"),
+            style: Styles::Note,
+        },
+        ..Default::default()
+    }
+}
+
+fn repeat_usage_filler(count: usize) -> PrintableRow {
+    PrintableRow {
+        bits: PrintableColumn::from("  "),
+        usage: PrintableColumn {
+            string: format!(
+                "Total of {} repeated usages, use --full to see all",
+                count + 1
+            ),
+            style: Styles::Note,
+        },
+        ..Default::default()
+    }
+}
+
+/// Print the parsed reports as an outline of how they look like
+fn print_report_summary(r: &impl Report, opts: &Options) {
+    if let Some(report_id) = r.report_id() {
+        Outfile::new().report_comment(r.report_id(), format!("Report ID: {report_id}").as_str());
+    }
+    Outfile::new().report_comment(
+        r.report_id(),
+        format!(" | Report size: {} bits", r.size_in_bits()).as_str(),
+    );
+
+    const REPEAT_LIMIT: usize = 3;
+
+    let mut last_usage: Usage = Usage::from(0);
+    let mut repeat_usage_count = 0;
+    let mut vendor_report_count = 0;
+    let mut table = PrintableTable::default();
+    for field in r.fields() {
+        let mut row = PrintableRow {
+            bits: PrintableColumn::from(bits_to_str(field.bits())),
+            ..Default::default()
+        };
+        if !opts.full && is_vendor_or_reserved_field(field) {
+            vendor_report_count += 1;
+        } else {
+            if vendor_report_count > REPEAT_LIMIT {
+                table.add(vendor_report_filler(vendor_report_count));
+            }
+            vendor_report_count = 0;
+        }
+        let row = match field {
+            Field::Constant(_c) => {
+                if repeat_usage_count > REPEAT_LIMIT {
+                    table.add(repeat_usage_filler(repeat_usage_count));
+                }
+                row.usage = "######### Padding".into();
+                Some(row)
+            }
+            Field::Variable(v) => {
+                if vendor_report_count <= REPEAT_LIMIT && repeat_usage_count <= REPEAT_LIMIT {
+                    row.usage = format!("Usage: {}", usage_to_str(&v.usage)).into();
+                    row.logical_range =
+                        logical_range_to_str(&v.logical_minimum, &v.logical_maximum).into();
+                    row.physical_range =
+                        physical_range_to_str(&v.physical_minimum, &v.physical_maximum).into();
+                    row.unit = unit_to_str(&v.unit).into();
+                }
+                if !opts.full && last_usage == v.usage {
+                    repeat_usage_count += 1;
+                } else {
+                    if repeat_usage_count > REPEAT_LIMIT {
+                        table.add(repeat_usage_filler(repeat_usage_count));
+                    }
+                    repeat_usage_count = 0;
+                }
+                last_usage = v.usage;
+                Some(row)
+            }
+            Field::Array(a) => {
+                if repeat_usage_count > REPEAT_LIMIT {
+                    table.add(repeat_usage_filler(repeat_usage_count));
+                }
+                row.usage = "Usages:".into();
+                row.logical_range =
+                    logical_range_to_str(&a.logical_minimum, &a.logical_maximum).into();
+                row.physical_range =
+                    physical_range_to_str(&a.physical_minimum, &a.physical_maximum).into();
+                row.unit = unit_to_str(&a.unit).into();
+                table.add(row);
+                let usages = a.usages().iter();
+                let usages = if opts.full {
+                    usages.take(0xffffffff)
+                } else {
+                    usages.take(MAX_USAGES_DISPLAYED)
+                };
+                usages.for_each(|u| {
+                    let row = PrintableRow {
+                        bits: PrintableColumn::from(" "),
+                        usage: PrintableColumn::from(usage_to_str(u)),
+                        ..Default::default()
+                    };
+                    table.add(row);
+                });
+                if !opts.full && a.usages().len() > MAX_USAGES_DISPLAYED {
+                    let row = PrintableRow {
+                        bits: PrintableColumn::from(" "),
+                        usage: PrintableColumn {
+                            string: "... use --full to see all usages".into(),
+                            style: Styles::Note,
+                        },
+                        ..Default::default()
+                    };
+                    table.add(row);
+                }
+                None
+            }
+        };
+        if vendor_report_count <= REPEAT_LIMIT && repeat_usage_count <= REPEAT_LIMIT {
+            if let Some(row) = row {
+                table.add(row);
+            }
+        }
+    }
+
+    if vendor_report_count > REPEAT_LIMIT {
+        table.add(vendor_report_filler(vendor_report_count));
+    } else if repeat_usage_count > REPEAT_LIMIT {
+        table.add(repeat_usage_filler(repeat_usage_count));
+    }
+    for row in table.rows {
+        let components = row
+            .columns()
+            .enumerate()
+            .map(|(idx, col)| {
+                (
+                    col.style.clone(),
+                    format!("{:w$} ", col.string, w = table.colwidths[idx]),
+                )
+            })
+            .collect::<Vec<(Styles, String)>>();
+        Outfile::new().report_comment_components(r.report_id(), components.as_slice());
+    }
+}
+
+pub fn parse_uevent(sysfs: &Path) -> Result<(String, (u32, u32, u32))> {
+    // uevent should contain
+    // HID_NAME=foo bar
+    // HID_ID=00003:0002135:0000123513
+    let uevent_path = sysfs.join("uevent");
+    let uevent = std::fs::read_to_string(uevent_path)?;
+
+    let name = uevent
+        .lines()
+        .find(|l| l.starts_with("HID_NAME"))
+        .context("Unable to find HID_NAME in uevent")?;
+    let (_, name) = name
+        .split_once('=')
+        .context("Unexpected HID_NAME= format")?;
+
+    let id = uevent
+        .lines()
+        .find(|l| l.starts_with("HID_ID"))
+        .context("Unable to find HID_ID in uevent")?;
+    let (_, id) = id.split_once('=').context("Unexpected HID_ID= format")?;
+    let ids: Vec<u32> = id
+        .split(':')
+        .map(|s| u32::from_str_radix(s, 16).context("Failed to parse {s} to int"))
+        .collect::<Result<Vec<u32>>>()
+        .context("Unable to parse HID_ID")?;
+    let (bustype, vid, pid) = (ids[0], ids[1], ids[2]);
+
+    Ok((name.to_string(), (bustype, vid, pid)))
+}
+
+/// Describe the device, `device` is its index in recordings of several
+/// devices
+fn parse_report_descriptor(
+    backend: &impl Backend,
+    device: Option<usize>,
+    opts: &Options,
+) -> Result<ReportDescriptor> {
+    // Reading the feature reports of a hidraw node sends a request to
+    // the device, only describing it should not have side effects
+    let feature_reports = if opts.only_describe {
+        &[]
+    } else {
+        backend.feature_reports()
+    };
+
+    if Outfile::output_format() == OutputFormat::Json {
+        return json::write_description(backend, feature_reports, device);
+    }
+
+    let name = backend.name();
+    let (bustype, vid, pid) = (backend.bustype(), backend.vid(), backend.pid());
+    let bytes = backend.rdesc();
+
+    Outfile::new().write_comment(name.to_string().as_str());
+    Outfile::new()
+        .write_comment(format!("Report descriptor length: {} bytes", bytes.len()).as_str());
+    print_rdesc_items(bytes)?;
+
+    // Print the readable fields
+    Outfile::new().write_report_descriptor(bytes);
+    Outfile::new().write_name(name);
+    Outfile::new().write_id(bustype, vid, pid);
+
+    let rdesc = ReportDescriptor::try_from(bytes as &[u8])?;
+    if !feature_reports.is_empty() {
+        Outfile::new().write_comment("Feature reports at the start of the recording:");
+        for f in feature_reports {
+            let event = ReportEvent::new(f, ReportDirection::Feature, &rdesc, Duration::ZERO, None);
+            if event.is_matched() {
+                print_report_event_description(&event, &rdesc, opts)?;
+            } else {
+                print_unmatched_report_warning(&event);
+            }
+            Outfile::new().write_feature_report(f);
+        }
+    }
+    Outfile::new().write_comment("Report descriptor:");
+    let input_reports = rdesc.input_reports();
+    if !input_reports.is_empty() {
+        for r in rdesc.input_reports() {
+            Outfile::new().write_comment_styled(Styles::InputItem, "------- Input Report ------- ");
+            print_report_summary(r, opts);
+        }
+    }
+    let output_reports = rdesc.output_reports();
+    if !output_reports.is_empty() {
+        for r in rdesc.output_reports() {
+            Outfile::new()
+                .write_comment_styled(Styles::OutputItem, "------- Output Report ------- ");
+            print_report_summary(r, opts);
+        }
+    }
+    let feature_reports = rdesc.feature_reports();
+    if !feature_reports.is_empty() {
+        for r in rdesc.feature_reports() {
+            Outfile::new()
+                .write_comment_styled(Styles::FeatureItem, "------- Feature Report ------- ");
+            print_report_summary(r, opts);
+        }
+    }
+
+    let nodes = backend.event_nodes();
+    if !nodes.is_empty() {
+        Outfile::new().separator();
+        Outfile::new().write_comment_styled(Styles::EventNodes, "Event nodes:");
+        for node in backend.event_nodes() {
+            Outfile::new().write_comment_styled(
+                Styles::EventNodes,
+                &format!(
+                    "- {:19} \"{}\"",
+                    format!("{}:", node.path().to_string_lossy()),
+                    node.name()
+                ),
+            );
+        }
+    }
+
+    Ok(rdesc)
+}
+
+fn get_hut_str(usage: &Usage) -> String {
+    let up: u16 = usage.usage_page.into();
+    let uid: u16 = usage.usage_id.into();
+    if let Ok(hut) = hut::Usage::new_from_page_and_id(up, uid) {
+        format!("{hut}")
+    } else {
+        format!(
+            "{:04x}/{:04x}",
+            u16::from(usage.usage_page),
+            u16::from(usage.usage_id)
+        )
+    }
+}
+
+/// Convert the logical value of a variable field to its physical value
+/// and unit, see Section 6.2.2.7 of the HID spec. Returns [None] where the
+/// field does not specify a unit.
+///
+/// The Unit Exponent is a 4-bit two's complement value where encoded as a
+/// nibble (0xe is 10⁻²) but some devices use a plain signed value instead,
+/// [UnitExponent::exponent] handles both.
+fn physical_value(field: &VariableField, value: i64) -> Option<(f64, String)> {
+    let unit = field.unit?;
+    // Unit::system() panics on reserved system nibbles
+    if u32::from(unit) & 0xf > 4 {
+        return None;
+    }
+    let units = unit.units()?;
+
+    let lmin = i32::from(field.logical_minimum) as f64;
+    let lmax = i32::from(field.logical_maximum) as f64;
+    // If the physical range is missing or both 0, it is the logical range
+    let (pmin, pmax) = match (field.physical_minimum, field.physical_maximum) {
+        (Some(min), Some(max)) if i32::from(min) != 0 || i32::from(max) != 0 => {
+            (i32::from(min) as f64, i32::from(max) as f64)
+        }
+        _ => (lmin, lmax),
+    };
+    let value = if lmax != lmin {
+        (value as f64 - lmin) * (pmax - pmin) / (lmax - lmin) + pmin
+    } else {
+        value as f64
+    };
+    let exponent = field.unit_exponent.map(|e| e.exponent()).unwrap_or(0);
+    let value = value * 10f64.powi(exponent.into());
+
+    // Lengths are easier to compare in mm, this is what e.g. libinput uses
+    match units.as_slice() {
+        [Units::Centimeter { exponent: 1 }] => Some((value * 10.0, "mm".to_string())),
+        _ => Some((
+            value,
+            units
+                .iter()
+                .map(|u| format!("{u}"))
+                .collect::<Vec<String>>()
+                .join(""),
+        )),
+    }
+}
+
+/// The field's value(s) as printed in the report description, `None` for
+/// fields printed as part of a preceding field
+fn print_field_values(field: &Field, decoded: &DecodedField, opts: &Options) -> Option<String> {
+    let hutstr = match &decoded.usage {
+        Some(usage) => get_hut_str(usage),
+        None => "<unknown>".to_string(),
+    };
+    let physical = |v: i64| match field {
+        Field::Variable(var) if opts.physical_units => physical_value(var, v),
+        _ => None,
+    };
+    let values = match &decoded.value {
+        DecodedValue::TextContinued => return None,
+        DecodedValue::Padding => {
+            format!("<{} bits padding>", decoded.bits.len())
+        }
+        DecodedValue::Unsigned(v) => match physical(i64::from(*v)) {
+            Some((p, unit)) => format!("{hutstr}: {p:.3} {unit}"),
+            None => format!("{hutstr}: {v:5}"),
+        },
+        DecodedValue::Signed(v) => match physical(i64::from(*v)) {
+            Some((p, unit)) => format!("{hutstr}: {p:.3} {unit}"),
+            None => format!("{hutstr}: {v:5}"),
+        },
+        DecodedValue::BigUnsigned(v) => format!("{hutstr}: {v} ({v:#x})"),
+        DecodedValue::BigSigned(v) => format!("{hutstr}: {v}"),
+        DecodedValue::Text(text) => format!("{hutstr}: {text:?}"),
+        DecodedValue::Bytes(data) => {
+            format!(
+                "{}: {}",
+                hutstr,
+                data.iter()
+                    .map(|v| format!("{v:02x}"))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            )
+        }
+        DecodedValue::Array(vs) => {
+            if decoded.usage.is_none() {
+                vs.iter()
+                    .map(|(v, usage)| {
+                        if let Some(usage) = usage {
+                            let hutstr = get_hut_str(usage);
+                            format!("{hutstr}: {v:5}")
+                        } else {
+                            // Let's just print the value as-is
+                            format!("{v:02x}")
+                        }
+                    })
+                    .collect::<Vec<String>>()
+                    .join("| ")
+            } else {
+                format!(
+                    "{hutstr}: {}",
+                    vs.iter()
+                        .fold("".to_string(), |acc, (b, _)| format!("{acc}{b:02x} "))
+                )
+            }
+        }
+    };
+    Some(values)
+}
+
+fn print_report_event_description(
+    event: &ReportEvent,
+    rdesc: &ReportDescriptor,
+    opts: &Options,
+) -> Result<()> {
+    let Some(report) = find_report(rdesc, event.direction, &event.bytes) else {
+        bail!("Unable to find matching {} report", event.direction);
+    };
+    if event.is_length_mismatch() {
+        Outfile::new().write_comment_styled(
+            Styles::Note,
+            &format!(
+                "Warning: {} report is {} bytes long, the report descriptor says {} bytes",
+                event.direction,
+                event.bytes.len(),
+                report.size_in_bytes()
+            ),
+        );
+    }
+    print_report_description(report, &event.fields, opts);
+    Ok(())
+}
+
+fn unmatched_report_warning(event: &ReportEvent) -> String {
+    format!(
+        "Warning: {} report of {} bytes does not match the report descriptor",
+        event.direction,
+        event.bytes.len()
+    )
+}
+
+fn print_unmatched_report_warning(event: &ReportEvent) {
+    Outfile::new().write_comment_styled(Styles::Note, &unmatched_report_warning(event));
+}
+
+fn print_report_description(report: &dyn Report, fields: &[DecodedField], opts: &Options) {
+    if let Some(id) = report.report_id() {
+        Outfile::new().report_comment(report.report_id(), format!(" Report ID: {id} / ").as_ref());
+    };
+
+    let collections: HashSet<&Collection> = report
+        .fields()
+        .iter()
+        .flat_map(|f| f.collections())
+        .filter(|c| matches!(c.collection_type(), CollectionType::Logical))
+        .collect();
+    if collections.is_empty() {
+        let msg = report
+            .fields()
+            .iter()
+            .zip(fields.iter())
+            .filter_map(|(f, decoded)| print_field_values(f, decoded, opts))
+            .collect::<Vec<String>>()
+            .join(" |");
+        Outfile::new().report_comment(report.report_id(), format!("              {msg}").as_str());
+    } else {
+        let mut collections: Vec<&Collection> = collections.into_iter().collect();
+        collections.sort_by(|a, b| a.id().partial_cmp(b.id()).unwrap());
+
+        for collection in collections {
+            let msg = report
+                .fields()
+                .iter()
+                .zip(fields.iter())
+                .filter(|(f, _)| {
+                    // logical collections may be nested, so we only group those items together
+                    // where the deepest logical collection matches
+                    f.collections()
+                        .iter()
+                        .rev()
+                        .find(|c| matches!(c.collection_type(), CollectionType::Logical))
+                        .map(|c| c == collection)
+                        .unwrap_or(false)
+                })
+                .filter_map(|(f, decoded)| print_field_values(f, decoded, opts))
+                .collect::<Vec<String>>()
+                .join(" |");
+            Outfile::new()
+                .report_comment(report.report_id(), format!("              {msg}").as_str());
+        }
+    }
+}
+
+/// The indices of the fields whose value differs from the previous event
+/// for the same report
+fn changed_fields(event: &ReportEvent, previous: &ReportEvent) -> Vec<usize> {
+    event
+        .fields
+        .iter()
+        .zip(previous.fields.iter())
+        .enumerate()
+        .filter(|(_, (decoded, prev))| decoded.value != prev.value)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Print only the fields whose value differs from the previous event for
+/// the same report
+fn print_changed_fields(
+    event: &ReportEvent,
+    previous: &ReportEvent,
+    rdesc: &ReportDescriptor,
+    opts: &Options,
+) -> Result<()> {
+    let Some(report) = find_report(rdesc, event.direction, &event.bytes) else {
+        bail!("Unable to find matching {} report", event.direction);
+    };
+    if let Some(id) = report.report_id() {
+        Outfile::new().report_comment(report.report_id(), format!(" Report ID: {id} / ").as_ref());
+    };
+    let changed = changed_fields(event, previous);
+    let msg = if changed.is_empty() {
+        "<no changes>".to_string()
+    } else {
+        changed
+            .iter()
+            .filter_map(|&idx| print_field_values(&report.fields()[idx], &event.fields[idx], opts))
+            .collect::<Vec<String>>()
+            .join(" |")
+    };
+    Outfile::new().report_comment(report.report_id(), format!("              {msg}").as_str());
+    Ok(())
+}
+
+/// Print the E: line for this event, highlighting any bytes that differ
+/// from the previous event's bytes (if any)
+fn print_input_report_data(event: &ReportEvent, previous: Option<&ReportEvent>) {
+    let elapsed = &event.elapsed;
+    let mut components = vec![(
+        Styles::Data,
+        format!(
+            "{:06}.{:06} {} ",
+            elapsed.as_secs(),
+            elapsed.as_micros() % 1000000,
+            event.bytes.len(),
+        ),
+    )];
+    components.extend(event.bytes.iter().enumerate().map(|(idx, b)| {
+        let changed = previous
+            .map(|p| p.bytes.get(idx) != Some(b))
+            .unwrap_or(false);
+        let style = if changed {
+            Styles::Changed
+        } else {
+            Styles::Data
+        };
+        (style, format!("{b:02x} "))
+    }));
+    Outfile::new().write_data_components(Prefix::Event, &components);
+}
+
+fn print_bpf_input_report_data(bytes: &[u8], elapsed: &Duration) {
+    let len = bytes.len();
+    let bytes = bytes
+        .iter()
+        .fold("".to_string(), |acc, b| format!("{acc}{b:02x} "));
+    Outfile::new().write_data(
+        Prefix::Bpf,
+        format!(
+            "{:06}.{:06} {} {}",
+            elapsed.as_secs(),
+            elapsed.as_micros() % 1000000,
+            len,
+            bytes,
+        )
+        .as_ref(),
+    );
+}
+
+fn print_current_time(last_timestamp: Option<Instant>) -> Option<Instant> {
+    let prev_timestamp = last_timestamp.unwrap_or(Instant::now());
+    let elapsed = prev_timestamp.elapsed().as_secs();
+    let now = chrono::prelude::Local::now();
+    if last_timestamp.is_none() || (elapsed > 1 && now.timestamp() % 5 == 0) {
+        Outfile::new().write_timestamp();
+        Some(Instant::now())
+    } else {
+        last_timestamp
+    }
+}
+
+/// Prints each [Event] in the selected [OutputFormat]
+#[derive(Default)]
+struct EventPrinter {
+    /// The index of the device in recordings of several devices
+    device: Option<usize>,
+    last_timestamp: Option<Instant>,
+    /// The number of reports that did not match the report descriptor
+    unmatched: usize,
+    /// The most recent event for each report, see [Options::changes_only]
+    previous: HashMap<(ReportDirection, Option<ReportId>), ReportEvent>,
+}
+
+impl EventPrinter {
+    fn print(&mut self, event: &Event, rdesc: &ReportDescriptor, opts: &Options) -> Result<()> {
+        match event {
+            Event::Report(e) => {
+                if Outfile::output_format() == OutputFormat::Json {
+                    json::write_event(e, self.device);
+                    return Ok(());
+                }
+                if e.walltime.is_some() {
+                    self.last_timestamp = print_current_time(self.last_timestamp);
+                }
+                let key = (e.direction, e.report_id);
+                let previous = if opts.changes_only {
+                    self.previous.get(&key)
+                } else {
+                    None
+                };
+                if !e.is_matched() {
+                    // Still record the bytes so we don't lose the event
+                    self.unmatched += 1;
+                    Outfile::new().write_comment_styled(
+                        Styles::Note,
+                        &format!(
+                            "{} ({} unmatched so far)",
+                            unmatched_report_warning(e),
+                            self.unmatched
+                        ),
+                    );
+                    print_input_report_data(e, None);
+                } else if let Some(previous) = previous {
+                    print_changed_fields(e, previous, rdesc, opts)?;
+                    print_input_report_data(e, Some(previous));
+                } else {
+                    print_report_event_description(e, rdesc, opts)?;
+                    print_input_report_data(e, None);
+                }
+                if opts.changes_only && e.is_matched() {
+                    self.previous.insert(key, e.clone());
+                }
+            }
+            Event::Bpf(e) => {
+                if Outfile::output_format() == OutputFormat::Json {
+                    json::write_bpf_event(e, self.device);
+                    return Ok(());
+                }
+                if e.walltime.is_some() {
+                    self.last_timestamp = print_current_time(self.last_timestamp);
+                }
+                // This prints the B: 123 00 01 02 ... data line
+                print_bpf_input_report_data(&e.bytes, &e.elapsed);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describe the device and, unless [Options::only_describe] is set,
+/// print its events.
+pub fn process(backend: impl Backend, opts: &Options) -> Result<()> {
+    let rdesc = parse_report_descriptor(&backend, None, opts)?;
+    if !opts.only_describe {
+        Outfile::new().separator();
+        Outfile::new().write_comment("Recorded events below in format:");
+        Outfile::new().write_comment("E: <seconds>.<microseconds> <length-in-bytes> [bytes ...]");
+        Outfile::new().write_comment("");
+        let mut printer = EventPrinter::default();
+        backend.read_events(opts.bpf, &rdesc, &mut |event| {
+            printer.print(&event, &rdesc, opts)
+        })?;
+        if printer.unmatched > 0 {
+            Outfile::new().write_comment_styled(
+                Styles::Note,
+                &format!(
+                    "{} reports did not match the report descriptor",
+                    printer.unmatched
+                ),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Like [process] but for several devices at once, e.g. all interfaces
+/// of a USB device. Each device's description and its events are
+/// prefixed with a `D: <index>` line, in JSON the records have a
+/// `"device"` field instead.
+///
+/// The devices are read in parallel and the events of live devices are
+/// timestamped relative to the first event of any device. A device that
+/// fails to read its events is skipped, the others keep recording.
+pub fn process_devices(backends: Vec<impl Backend + Send + 'static>, opts: &Options) -> Result<()> {
+    let mut rdescs = Vec::new();
+    for (idx, backend) in backends.iter().enumerate() {
+        Outfile::new().write_device_index(idx);
+        rdescs.push(parse_report_descriptor(backend, Some(idx), opts)?);
+    }
+    if opts.only_describe {
+        return Ok(());
+    }
+
+    Outfile::new().separator();
+    Outfile::new().write_comment("Recorded events below in format:");
+    Outfile::new().write_comment("E: <seconds>.<microseconds> <length-in-bytes> [bytes ...]");
+    Outfile::new().write_comment("");
+
+    // Live devices only return on error, so the threads are not joined
+    let (tx, rx) = std::sync::mpsc::channel::<(usize, Result<Event>)>();
+    for (idx, backend) in backends.into_iter().enumerate() {
+        let tx = tx.clone();
+        let bpf = opts.bpf;
+        std::thread::spawn(move || {
+            let result = ReportDescriptor::try_from(backend.rdesc())
+                .map_err(anyhow::Error::from)
+                .and_then(|rdesc| {
+                    backend.read_events(bpf, &rdesc, &mut |event| {
+                        // The receiver only goes away on error
+                        tx.send((idx, Ok(event)))
+                            .context("Failed to pass on the event")
+                    })
+                });
+            if let Err(e) = result {
+                let _ = tx.send((idx, Err(e)));
+            }
+        });
+    }
+    drop(tx);
+
+    let mut printers: Vec<EventPrinter> = (0..rdescs.len())
+        .map(|idx| EventPrinter {
+            device: Some(idx),
+            ..Default::default()
+        })
+        .collect();
+    let mut start: Option<SystemTime> = None;
+    let mut last_device = None;
+    for (idx, event) in rx.iter() {
+        // One device failing should not stop the recording of the others
+        let mut event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                Outfile::new().write_comment_styled(
+                    Styles::Note,
+                    &format!("Device {idx} stopped recording: {e:#}"),
+                );
+                continue;
+            }
+        };
+        let (elapsed, walltime) = match &mut event {
+            Event::Report(e) => (&mut e.elapsed, e.walltime),
+            Event::Bpf(e) => (&mut e.elapsed, e.walltime),
+        };
+        if let Some(walltime) = walltime {
+            let start = *start.get_or_insert(walltime);
+            *elapsed = walltime.duration_since(start).unwrap_or_default();
+        }
+        if last_device != Some(idx) {
+            Outfile::new().write_device_index(idx);
+            last_device = Some(idx);
+        }
+        printers[idx].print(&event, &rdescs[idx], opts)?;
+    }
+    for (idx, printer) in printers.iter().enumerate() {
+        if printer.unmatched > 0 {
+            Outfile::new().write_comment_styled(
+                Styles::Note,
+                &format!(
+                    "{} reports of device {idx} did not match the report descriptor",
+                    printer.unmatched
+                ),
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keyboard with 8 modifier bits in input report 1 and three LEDs
+    // plus 5 bits padding in output report 1
+    const KEYBOARD: [u8; 39] = [
+        0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x85, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15,
+        0x00, 0x25, 0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x05, 0x08, 0x19, 0x01, 0x29, 0x03,
+        0x95, 0x03, 0x91, 0x02, 0x95, 0x05, 0x91, 0x01, 0xc0,
+    ];
+
+    /// Run `f` and return everything it wrote to the [Outfile] in this
+    /// thread, without the color escape sequences
+    pub(crate) fn capture_output(f: impl FnOnce()) -> String {
+        CAPTURED_OUTPUT.with(|c| c.replace(Some(Vec::new())));
+        f();
+        let output = String::from_utf8(CAPTURED_OUTPUT.with(|c| c.take()).unwrap()).unwrap();
+        let mut text = String::new();
+        let mut chars = output.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.find(|c| *c == 'm');
+            } else {
+                text.push(c);
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn test_find_hidraw() {
+        let hidraws: Vec<String> = std::fs::read_dir("/dev/")
+            .unwrap()
+            .flatten()
+            .flat_map(|f| f.file_name().into_string())
+            .filter(|name| name.starts_with("hidraw"))
+            .collect();
+        for hidraw in hidraws.iter().map(|h| PathBuf::from("/dev/").join(h)) {
+            let result = hidraw::HidrawBackend::try_from(hidraw.as_path());
+            assert!(result.is_ok());
+        }
+    }
+
+    #[test]
+    fn test_find_event_node() {
+        // We can't assume any local event nodes, and even where we have them
+        // they're not backed by HID devices.
+        if std::env::var("CI").is_ok() {
+            return;
+        }
+        let evdevs: Vec<String> = std::fs::read_dir("/dev/input")
+            .unwrap()
+            .flatten()
+            .flat_map(|f| f.file_name().into_string())
+            .filter(|name| name.starts_with("event"))
+            .collect();
+        if !evdevs.is_empty() {
+            assert!(evdevs
+                .iter()
+                .map(|n| PathBuf::from("/dev/input").join(n))
+                .any(|evdev| hidraw::HidrawBackend::try_from(evdev.as_path()).is_ok()));
+        }
+    }
+
+    // Make sure we can always parse the devices currently plugged into
+    // this machine.
+    #[test]
+    fn test_parse_local_hid_reports() {
+        let hidraws: Vec<String> = std::fs::read_dir("/dev/")
+            .unwrap()
+            .flatten()
+            .flat_map(|f| f.file_name().into_string())
+            .filter(|name| name.starts_with("hidraw"))
+            .collect();
+        for (path, backend) in hidraws
+            .iter()
+            .map(|h| PathBuf::from("/dev/").join(h))
+            .map(|path| {
+                (
+                    path.clone(),
+                    hidraw::HidrawBackend::try_from(path.as_path()).unwrap(),
+                )
+            })
+        {
+            let opts = Options {
+                full: true,
+                only_describe: true,
+                ..Default::default()
+            };
+            parse_report_descriptor(&backend, None, &opts)
+                .unwrap_or_else(|_| panic!("Failed to parse {path:?}"));
+        }
+    }
+
+    #[test]
+    fn test_decode_output_report() {
+        let rdesc = ReportDescriptor::try_from(KEYBOARD.as_slice()).unwrap();
+
+        let event = ReportEvent::new(
+            &[0x01, 0b011],
+            ReportDirection::Output,
+            &rdesc,
+            Duration::ZERO,
+            None,
+        );
+        assert!(event.is_matched());
+        let leds: Vec<(String, DecodedValue)> = event
+            .fields
+            .iter()
+            .filter_map(|f| f.usage.map(|u| (get_hut_str(&u), f.value.clone())))
+            .collect();
+        assert_eq!(
+            leds,
+            vec![
+                ("Num Lock".to_string(), DecodedValue::Unsigned(1)),
+                ("Caps Lock".to_string(), DecodedValue::Unsigned(1)),
+                ("Scroll Lock".to_string(), DecodedValue::Unsigned(0)),
+            ]
+        );
+
+        assert!(find_report(&rdesc, ReportDirection::Feature, &[0x01]).is_none());
+        assert!(find_report(&rdesc, ReportDirection::Output, &[0x02]).is_none());
+
+        // Short and long reports are decoded but kept as-is
+        let event = ReportEvent::new(
+            &[0x01],
+            ReportDirection::Output,
+            &rdesc,
+            Duration::ZERO,
+            None,
+        );
+        assert!(event.is_length_mismatch());
+        assert_eq!(event.bytes, vec![0x01]);
+        assert_eq!(event.fields[0].value, DecodedValue::Unsigned(0));
+        let event = ReportEvent::new(
+            &[0x01, 0b001, 0xff],
+            ReportDirection::Output,
+            &rdesc,
+            Duration::ZERO,
+            None,
+        );
+        assert!(event.is_length_mismatch());
+        assert_eq!(event.bytes, vec![0x01, 0b001, 0xff]);
+        assert_eq!(event.fields[0].value, DecodedValue::Unsigned(1));
+
+        let event = ReportEvent::new(
+            &[0x02, 0x01],
+            ReportDirection::Input,
+            &rdesc,
+            Duration::ZERO,
+            None,
+        );
+        assert!(!event.is_matched());
+        assert_eq!(event.bytes, vec![0x02, 0x01]);
+        assert!(event.fields.is_empty());
+    }
+
+    #[test]
+    fn test_changed_fields() {
+        let rdesc = ReportDescriptor::try_from(KEYBOARD.as_slice()).unwrap();
+        let decode = |bytes: &[u8], direction| {
+            ReportEvent::new(bytes, direction, &rdesc, Duration::ZERO, None)
+        };
+
+        let first = decode(&[0x01, 0b001], ReportDirection::Input);
+        let second = decode(&[0x01, 0b101], ReportDirection::Input);
+        assert_eq!(changed_fields(&second, &first), vec![2]);
+        // Printed as <no changes>
+        assert!(changed_fields(&second, &second).is_empty());
+
+        // The output report with the same report ID does not replace the
+        // previous input report
+        let opts = Options {
+            changes_only: true,
+            ..Default::default()
+        };
+        let mut printer = EventPrinter::default();
+        let output = capture_output(|| {
+            for event in [
+                first,
+                decode(&[0x01, 0b011], ReportDirection::Output),
+                second.clone(),
+                second.clone(),
+            ] {
+                printer.print(&Event::Report(event), &rdesc, &opts).unwrap();
+            }
+        });
+        let fields: Vec<&str> = output
+            .lines()
+            .filter(|l| !l.contains("Report ID") && !l.starts_with("E: "))
+            .map(|l| l.trim_start_matches(['#', ' ', '░']).trim_end())
+            .collect();
+        assert_eq!(fields[2], "Keyboard LeftAlt:     1");
+        assert_eq!(fields[3], "<no changes>");
+        assert_eq!(printer.previous.len(), 2);
+        let previous = &printer.previous[&(ReportDirection::Input, second.report_id)];
+        assert_eq!(previous.bytes, second.bytes);
+        let previous = &printer.previous[&(ReportDirection::Output, second.report_id)];
+        assert_eq!(previous.bytes, vec![0x01, 0b011]);
+    }
+
+    #[test]
+    fn test_unmatched_report() {
+        let rdesc = ReportDescriptor::try_from(KEYBOARD.as_slice()).unwrap();
+        let event = ReportEvent::new(
+            &[0x02, 0xaa, 0xbb],
+            ReportDirection::Input,
+            &rdesc,
+            Duration::from_micros(1_500),
+            None,
+        );
+        assert!(!event.is_matched());
+
+        let mut printer = EventPrinter::default();
+        let output = capture_output(|| {
+            for _ in 0..2 {
+                printer
+                    .print(&Event::Report(event.clone()), &rdesc, &Options::default())
+                    .unwrap();
+            }
+        });
+        assert_eq!(printer.unmatched, 2);
+        assert!(output.contains("(2 unmatched so far)"));
+        let data: Vec<&str> = output
+            .lines()
+            .filter(|l| l.starts_with("E: "))
+            .map(str::trim_end)
+            .collect();
+        assert_eq!(data, vec!["E: 000000.001500 3 02 aa bb"; 2]);
+    }
+
+    #[test]
+    fn test_decode_wide_values() {
+        // A 48 bit counter at bit offset 4
+        let bytes = [0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0x0c];
+        assert_eq!(
+            extract_bits(&bytes, &(4..52)),
+            vec![0x21, 0x43, 0x65, 0x87, 0xa9, 0xcb]
+        );
+        let usage = Usage::from(0xff000001);
+        assert_eq!(
+            decode_wide_value(&bytes, &(4..52), &usage, false),
+            DecodedValue::BigUnsigned(0xcba987654321)
+        );
+        // Same bits, but signed and the top bit is set
+        assert_eq!(
+            decode_wide_value(&bytes, &(4..52), &usage, true),
+            DecodedValue::BigSigned(0xcba987654321 - (1 << 48))
+        );
+        // Bits past the end of the report are zero
+        assert_eq!(extract_bits(&[0xff], &(4..16)), vec![0x0f, 0x00]);
+
+        let wide = [0xffu8; 20];
+        assert_eq!(
+            decode_wide_value(&wide, &(0..136), &usage, false),
+            DecodedValue::Bytes(vec![0xff; 17])
+        );
+
+        // Sensors / Property: Sensor Model as UTF-16, zero-padded
+        let usage = Usage::from(0x00200306);
+        let bytes = [b'a', 0, b'b', 0, 0, 0];
+        assert_eq!(
+            decode_wide_value(&bytes, &(0..48), &usage, false),
+            DecodedValue::Text("ab".to_string())
+        );
+    }
+
+    #[test]
+    fn test_join_text_fields() {
+        let bytes = [
+            0x05, 0x20, // Usage Page (Sensor)
+            0x09, 0x01, // Usage (Sensor)
+            0xa1, 0x01, // Collection (Application)
+            0x0a, 0x01, 0x03, // Usage (Property: Friendly Name)
+            0x15, 0x00, // Logical Minimum (0)
+            0x27, 0xff, 0xff, 0x00, 0x00, // Logical Maximum (65535)
+            0x75, 0x10, // Report Size (16)
+            0x95, 0x04, // Report Count (4)
+            0xb1, 0x02, // Feature (Data,Var,Abs)
+            0xc0, // End Collection
+        ];
+        let rdesc = ReportDescriptor::try_from(bytes.as_slice()).unwrap();
+        let event = ReportEvent::new(
+            &[b'H', 0, b'i', 0, 0, 0, 0, 0],
+            ReportDirection::Feature,
+            &rdesc,
+            Duration::ZERO,
+            None,
+        );
+        let values: Vec<&DecodedValue> = event.fields.iter().map(|f| &f.value).collect();
+        assert_eq!(
+            values,
+            vec![
+                &DecodedValue::Text("Hi".to_string()),
+                &DecodedValue::TextContinued,
+                &DecodedValue::TextContinued,
+                &DecodedValue::TextContinued,
+            ]
+        );
+
+        let report = rdesc.feature_reports().first().unwrap();
+        let printed: Vec<String> = report
+            .fields()
+            .iter()
+            .zip(event.fields.iter())
+            .filter_map(|(f, decoded)| print_field_values(f, decoded, &Options::default()))
+            .collect();
+        assert_eq!(printed.len(), 1);
+        assert!(printed[0].ends_with(": \"Hi\""));
+    }
+
+    #[test]
+    fn test_physical_value() {
+        let bytes = [
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x02, // Usage (Mouse)
+            0xa1, 0x01, // Collection (Application)
+            0x09, 0x30, //   Usage (X)
+            0x15, 0x00, //   Logical Minimum (0)
+            0x26, 0xa0, 0x0f, //   Logical Maximum (4000)
+            0x35, 0x00, //   Physical Minimum (0)
+            0x46, 0xe8, 0x03, //   Physical Maximum (1000)
+            0x65, 0x11, //   Unit (Centimeter)
+            0x55, 0x0d, //   Unit Exponent (-3)
+            0x75, 0x10, //   Report Size (16)
+            0x95, 0x01, //   Report Count (1)
+            0x81, 0x02, //   Input (Data,Var,Abs)
+            0x09, 0x35, //   Usage (Rz)
+            0x26, 0x67, 0x01, //   Logical Maximum (359)
+            0x45, 0x00, //   Physical Maximum (0)
+            0x65, 0x14, //   Unit (Degrees)
+            0x55, 0x00, //   Unit Exponent (0)
+            0x81, 0x02, //   Input (Data,Var,Abs)
+            0x09, 0x31, //   Usage (Y)
+            0x65, 0x00, //   Unit (None)
+            0x81, 0x02, //   Input (Data,Var,Abs)
+            0xc0, // End Collection
+        ];
+        let rdesc = ReportDescriptor::try_from(bytes.as_slice()).unwrap();
+        let report = rdesc.input_reports().first().unwrap();
+        let fields: Vec<&VariableField> = report
+            .fields()
+            .iter()
+            .map(|f| match f {
+                Field::Variable(var) => var,
+                _ => panic!("Expected a variable field"),
+            })
+            .collect();
+
+        // 2000/4000 of 1000 * 10⁻³ cm
+        let (value, unit) = physical_value(fields[0], 2000).unwrap();
+        assert!((value - 5.0).abs() < 1e-9);
+        assert_eq!(unit, "mm");
+        // No physical range, so the logical range applies
+        let (value, unit) = physical_value(fields[1], 90).unwrap();
+        assert!((value - 90.0).abs() < 1e-9);
+        assert_eq!(unit, "deg");
+        assert!(physical_value(fields[2], 10).is_none());
+
+        let event = ReportEvent::new(
+            &[0xd0, 0x07, 0x5a, 0x00, 0x0a, 0x00],
+            ReportDirection::Input,
+            &rdesc,
+            Duration::ZERO,
+            None,
+        );
+        let opts = Options {
+            physical_units: true,
+            ..Default::default()
+        };
+        let values: Vec<String> = report
+            .fields()
+            .iter()
+            .zip(event.fields.iter())
+            .filter_map(|(f, decoded)| print_field_values(f, decoded, &opts))
+            .collect();
+        assert_eq!(values, vec!["X: 5.000 mm", "Rz: 90.000 deg", "Y:    10"]);
+    }
+}
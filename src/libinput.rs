@@ -8,8 +8,7 @@ use std::time::Duration;
 use yaml_rust2::{Yaml, YamlLoader};
 
 use crate::{
-    print_input_report_data, print_input_report_description, Backend, BpfOption, EventNode,
-    ReportDescriptor,
+    Backend, BpfOption, Event, EventNode, Outfile, ReportDescriptor, ReportDirection, ReportEvent,
 };
 
 #[derive(Debug)]
@@ -190,14 +189,24 @@ impl Backend for LibinputRecordingBackend {
         &[]
     }
 
-    fn read_events(&self, _use_bpf: BpfOption, rdesc: &ReportDescriptor) -> Result<()> {
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        rdesc: &ReportDescriptor,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
         if self.events.is_empty() {
-            println!("# No events found in this recording");
+            Outfile::new().write_comment("No events found in this recording");
         }
         for e in self.events.iter() {
             let elapsed = Duration::from_micros(e.usecs);
-            print_input_report_description(&e.bytes, rdesc)?;
-            print_input_report_data(&e.bytes, rdesc, &elapsed)?;
+            callback(Event::Report(ReportEvent::new(
+                &e.bytes,
+                ReportDirection::Input,
+                rdesc,
+                elapsed,
+                None,
+            )))?;
         }
 
         Ok(())
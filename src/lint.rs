@@ -0,0 +1,560 @@
+// SPDX-License-Identifier: MIT
+//
+// Check a report descriptor for common bugs. Each finding carries the
+// byte offset of the offending item, the same offset that is printed
+// in the report descriptor listing.
+
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+
+use crate::{json, walk_rdesc_items, Backend, Outfile, OutputFormat, ReportDirection, Styles};
+use hidreport::hid::{GlobalItem, Item, ItemType, LocalItem, MainItem, ReportDescriptorItems};
+use hidreport::*;
+
+/// The kernel's limits, see drivers/hid/hid-core.c
+const HID_MAX_BUFFER_SIZE: usize = 16384;
+const HID_MAX_USAGES: usize = 12288;
+const HID_MAX_REPORT_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found in the report descriptor
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    /// The byte offset of the item in the report descriptor, if the
+    /// finding can be attributed to a single item
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct LintOptions {
+    /// The maximum packet size of the device's interrupt endpoint. Reports
+    /// larger than this are flagged if set.
+    pub endpoint_size: Option<usize>,
+}
+
+/// The global items that apply to a main item, saved and restored by
+/// Push and Pop
+#[derive(Clone, Copy, Default)]
+struct Globals {
+    report_id: Option<u8>,
+    report_size: usize,
+    report_count: usize,
+    /// The offset and the signed value of the Logical Minimum
+    logical_minimum: Option<(usize, i64)>,
+    /// The offset and the unsigned and signed value of the Logical Maximum
+    logical_maximum: Option<(usize, u32, i64)>,
+}
+
+/// The size of one report, accumulated from its main items
+struct ReportSize {
+    direction: ReportDirection,
+    report_id: Option<u8>,
+    /// The offset of the first main item of this report
+    offset: usize,
+    bits: usize,
+    /// Whether a single main item already exceeds the kernel's limit
+    too_long: bool,
+}
+
+/// Interpret the item's data bytes as signed value
+fn signed_data(item: &impl Item) -> i64 {
+    match item.data().as_deref() {
+        Some([b]) => i64::from(*b as i8),
+        Some([b0, b1]) => i64::from(i16::from_le_bytes([*b0, *b1])),
+        Some([b0, b1, b2, b3]) => i64::from(i32::from_le_bytes([*b0, *b1, *b2, *b3])),
+        _ => 0,
+    }
+}
+
+/// Interpret the item's data bytes as unsigned value
+fn unsigned_data(item: &impl Item) -> u32 {
+    item.data()
+        .and_then(|d| u32::try_from(&d).ok())
+        .unwrap_or(0)
+}
+
+/// Compare usage minimum and maximum, ignoring the usage page unless
+/// both specify one
+fn usage_range_is_inverted(min: u32, max: u32) -> bool {
+    if (min > 0xffff) == (max > 0xffff) {
+        min > max
+    } else {
+        (min & 0xffff) > (max & 0xffff)
+    }
+}
+
+fn add(findings: &mut Vec<Finding>, severity: Severity, offset: Option<usize>, message: String) {
+    findings.push(Finding {
+        severity,
+        offset,
+        message,
+    });
+}
+
+/// Walk the report descriptor items and return all findings, sorted by
+/// their offset.
+pub fn lint(bytes: &[u8], opts: &LintOptions) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = Vec::new();
+
+    let items = match ReportDescriptorItems::try_from(bytes) {
+        Ok(items) => items,
+        Err(e) => {
+            let offset = match e {
+                ParserError::InvalidData { offset, .. } => Some(offset),
+                _ => None,
+            };
+            add(
+                &mut findings,
+                Severity::Error,
+                offset,
+                format!("Unable to parse the report descriptor: {e}"),
+            );
+            return findings;
+        }
+    };
+
+    let mut globals = Globals::default();
+    let mut global_stack: Vec<(usize, Globals)> = Vec::new();
+    let mut collections: Vec<usize> = Vec::new();
+    let mut usage_minimum: Option<(usize, u32)> = None;
+    let mut reports: Vec<ReportSize> = Vec::new();
+    // Main data items declared while no Report ID was set
+    let mut items_without_id: Vec<(usize, ReportDirection)> = Vec::new();
+    let mut uses_report_ids = false;
+    // Offsets of the Logical Maximum items we already flagged, the same
+    // item applies to all main items that follow it
+    let mut flagged_maximums: Vec<usize> = Vec::new();
+
+    for rdesc_item in items.iter() {
+        let offset = rdesc_item.offset();
+        let item = rdesc_item.item();
+        match item.item_type() {
+            ItemType::Main(MainItem::Collection(_)) => collections.push(offset),
+            ItemType::Main(MainItem::EndCollection) if collections.pop().is_none() => add(
+                &mut findings,
+                Severity::Error,
+                Some(offset),
+                "End Collection without a matching Collection".into(),
+            ),
+            ItemType::Main(MainItem::EndCollection) => {}
+            ItemType::Main(main) => {
+                let direction = match main {
+                    MainItem::Input(_) => ReportDirection::Input,
+                    MainItem::Output(_) => ReportDirection::Output,
+                    _ => ReportDirection::Feature,
+                };
+
+                if let (Some((_, lmin)), Some((max_offset, umax, smax))) =
+                    (globals.logical_minimum, globals.logical_maximum)
+                {
+                    // The kernel interprets the Logical Maximum as signed
+                    // if the Logical Minimum is negative
+                    let message = if lmin < 0 && i64::from(umax) != smax {
+                        Some(format!(
+                            "Logical Maximum ({umax}) is only valid as unsigned but the Logical Minimum ({lmin}) is negative, so it is interpreted as {smax}"
+                        ))
+                    } else if lmin >= 0 && i64::from(umax) < lmin {
+                        Some(format!(
+                            "Logical Maximum ({umax}) is less than the Logical Minimum ({lmin})"
+                        ))
+                    } else {
+                        None
+                    };
+                    if let Some(message) = message {
+                        if !flagged_maximums.contains(&max_offset) {
+                            flagged_maximums.push(max_offset);
+                            add(&mut findings, Severity::Error, Some(max_offset), message);
+                        }
+                    }
+                }
+
+                // The kernel rejects any report longer than its buffer
+                // minus the byte for the Report ID
+                let bits = globals.report_size.saturating_mul(globals.report_count);
+                let too_long = bits > (HID_MAX_BUFFER_SIZE - 1) * 8;
+                if too_long {
+                    add(
+                        &mut findings,
+                        Severity::Error,
+                        Some(offset),
+                        format!(
+                            "Report Size ({}) × Report Count ({}) is {bits} bits, larger than the kernel's limit of {} bits per report",
+                            globals.report_size,
+                            globals.report_count,
+                            (HID_MAX_BUFFER_SIZE - 1) * 8
+                        ),
+                    );
+                }
+                match reports
+                    .iter_mut()
+                    .find(|r| r.direction == direction && r.report_id == globals.report_id)
+                {
+                    Some(r) => {
+                        r.bits = r.bits.saturating_add(bits);
+                        r.too_long |= too_long;
+                    }
+                    None => reports.push(ReportSize {
+                        direction,
+                        report_id: globals.report_id,
+                        offset,
+                        // The Report ID is the first byte of the report
+                        bits: bits.saturating_add(if globals.report_id.is_some() { 8 } else { 0 }),
+                        too_long,
+                    }),
+                }
+                if globals.report_id.is_none() {
+                    items_without_id.push((offset, direction));
+                }
+            }
+            ItemType::Global(GlobalItem::Push) => global_stack.push((offset, globals)),
+            ItemType::Global(GlobalItem::Pop) => match global_stack.pop() {
+                Some((_, g)) => globals = g,
+                None => add(
+                    &mut findings,
+                    Severity::Error,
+                    Some(offset),
+                    "Pop without a matching Push".into(),
+                ),
+            },
+            ItemType::Global(GlobalItem::ReportId(id)) => {
+                let id = u8::from(id);
+                if id == 0 {
+                    add(
+                        &mut findings,
+                        Severity::Error,
+                        Some(offset),
+                        "Report ID 0 is reserved".into(),
+                    );
+                }
+                globals.report_id = Some(id);
+                uses_report_ids = true;
+            }
+            ItemType::Global(GlobalItem::ReportSize(size)) => {
+                globals.report_size = usize::from(size);
+                if globals.report_size > HID_MAX_REPORT_SIZE {
+                    add(
+                        &mut findings,
+                        Severity::Error,
+                        Some(offset),
+                        format!(
+                            "Report Size ({}) is larger than the kernel's limit of {HID_MAX_REPORT_SIZE} bits",
+                            globals.report_size
+                        ),
+                    );
+                }
+            }
+            ItemType::Global(GlobalItem::ReportCount(count)) => {
+                globals.report_count = usize::from(count);
+                if globals.report_count > HID_MAX_USAGES {
+                    add(
+                        &mut findings,
+                        Severity::Error,
+                        Some(offset),
+                        format!(
+                            "Report Count ({}) is larger than the kernel's limit of {HID_MAX_USAGES}",
+                            globals.report_count
+                        ),
+                    );
+                }
+            }
+            ItemType::Global(GlobalItem::LogicalMinimum(_)) => {
+                globals.logical_minimum = Some((offset, signed_data(item)));
+            }
+            ItemType::Global(GlobalItem::LogicalMaximum(_)) => {
+                globals.logical_maximum = Some((offset, unsigned_data(item), signed_data(item)));
+            }
+            ItemType::Global(GlobalItem::Reserved) => add(
+                &mut findings,
+                Severity::Error,
+                Some(offset),
+                format!("Reserved global item tag {:#04x}", item.header() & 0xfc),
+            ),
+            ItemType::Local(LocalItem::UsageMinimum(min)) => {
+                usage_minimum = Some((offset, u32::from(min)));
+            }
+            ItemType::Local(LocalItem::UsageMaximum(max)) => {
+                let max = u32::from(max);
+                if let Some((min_offset, min)) = usage_minimum {
+                    if usage_range_is_inverted(min, max) {
+                        add(
+                            &mut findings,
+                            Severity::Error,
+                            Some(min_offset),
+                            format!(
+                                "Usage Minimum ({min:#x}) is greater than the Usage Maximum ({max:#x})"
+                            ),
+                        );
+                    }
+                }
+            }
+            ItemType::Local(LocalItem::Reserved { value }) => add(
+                &mut findings,
+                Severity::Error,
+                Some(offset),
+                format!("Reserved local item tag {value:#04x}"),
+            ),
+            ItemType::Long => add(
+                &mut findings,
+                Severity::Warning,
+                Some(offset),
+                "Long items are not defined by the HID specification".into(),
+            ),
+            ItemType::Reserved => add(
+                &mut findings,
+                Severity::Error,
+                Some(offset),
+                format!("Reserved item type in header {:#04x}", item.header()),
+            ),
+            _ => {}
+        }
+
+        // Local items only apply to the next main item
+        if let ItemType::Main(_) = item.item_type() {
+            usage_minimum = None;
+        }
+    }
+
+    for offset in collections {
+        add(
+            &mut findings,
+            Severity::Error,
+            Some(offset),
+            "Collection without a matching End Collection".into(),
+        );
+    }
+    for (offset, _) in global_stack {
+        add(
+            &mut findings,
+            Severity::Error,
+            Some(offset),
+            "Push without a matching Pop".into(),
+        );
+    }
+    if uses_report_ids {
+        for (offset, direction) in items_without_id {
+            add(
+                &mut findings,
+                Severity::Error,
+                Some(offset),
+                format!(
+                    "{direction} item without a Report ID in a report descriptor that uses Report IDs"
+                ),
+            );
+        }
+    }
+
+    for report in reports.iter() {
+        let size = (report.bits + 7) / 8;
+        let report_str = match report.report_id {
+            Some(id) => format!("{} report {id}", report.direction),
+            None => format!("{} report", report.direction),
+        };
+        if size > HID_MAX_BUFFER_SIZE && !report.too_long {
+            add(
+                &mut findings,
+                Severity::Error,
+                Some(report.offset),
+                format!(
+                    "{report_str} is {size} bytes, larger than the kernel's limit of {HID_MAX_BUFFER_SIZE} bytes"
+                ),
+            );
+        }
+        if let Some(endpoint_size) = opts.endpoint_size {
+            if report.direction == ReportDirection::Input && size > endpoint_size {
+                add(
+                    &mut findings,
+                    Severity::Warning,
+                    Some(report.offset),
+                    format!(
+                        "{report_str} is {size} bytes, larger than the endpoint size of {endpoint_size} bytes"
+                    ),
+                );
+            }
+        }
+    }
+
+    // Anything our item walk missed is still caught by the parser
+    if findings.iter().all(|f| f.severity != Severity::Error) {
+        if let Err(e) = ReportDescriptor::try_from(bytes) {
+            add(
+                &mut findings,
+                Severity::Error,
+                None,
+                format!("Unable to parse the report descriptor: {e}"),
+            );
+        }
+    }
+
+    findings.sort_by_key(|f| f.offset);
+    findings
+}
+
+/// Lint the backend's report descriptor and print the findings. Returns an
+/// error if any of the findings is an error.
+pub fn lint_backend(backend: &impl Backend, opts: &LintOptions) -> Result<()> {
+    let bytes = backend.rdesc();
+    let findings = lint(bytes, opts);
+
+    if Outfile::output_format() == OutputFormat::Json {
+        for f in findings.iter() {
+            json::write_lint_finding(f);
+        }
+    } else {
+        // The description of each item, as printed in the item listing
+        let mut descriptions: HashMap<usize, String> = HashMap::new();
+        let _ = walk_rdesc_items(bytes, |rdesc_item, description, _| {
+            let bytes = rdesc_item
+                .item()
+                .bytes()
+                .iter()
+                .map(|b| format!("0x{b:02x}, "))
+                .collect::<Vec<String>>()
+                .join("");
+            descriptions.insert(rdesc_item.offset(), format!("{bytes:30} // {description}"));
+        });
+
+        Outfile::new().write_comment(backend.name());
+        for f in findings.iter() {
+            let style = match f.severity {
+                Severity::Error => Styles::Note,
+                Severity::Warning => Styles::None,
+            };
+            match f.offset {
+                Some(offset) => {
+                    Outfile::new().write_comment_styled(
+                        style,
+                        &format!("{} at offset {offset}: {}", f.severity, f.message),
+                    );
+                    if let Some(description) = descriptions.get(&offset) {
+                        Outfile::new().write_comment(&format!("    {description}"));
+                    }
+                }
+                None => {
+                    Outfile::new()
+                        .write_comment_styled(style, &format!("{}: {}", f.severity, f.message));
+                }
+            }
+        }
+        Outfile::new().write_comment(&format!(
+            "{} problems found in {} bytes",
+            findings.len(),
+            bytes.len()
+        ));
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        bail!("The report descriptor has {errors} errors");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(bytes: &[u8], opts: &LintOptions) -> Vec<(Option<usize>, String)> {
+        lint(bytes, opts)
+            .into_iter()
+            .map(|f| (f.offset, f.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_clean() {
+        // A boot protocol mouse with no problems and a single 3 byte report
+        let bytes = [
+            0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01,
+            0x29, 0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01,
+            0x75, 0x05, 0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f,
+            0x75, 0x08, 0x95, 0x02, 0x81, 0x06, 0xc0, 0xc0,
+        ];
+        assert!(lint(&bytes, &LintOptions::default()).is_empty());
+
+        // 3 byte report
+        let opts = LintOptions {
+            endpoint_size: Some(2),
+        };
+        assert_eq!(
+            messages(&bytes, &opts),
+            vec![(
+                Some(24),
+                "input report is 3 bytes, larger than the endpoint size of 2 bytes".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_lint_findings() {
+        let bytes = [
+            0x05, 0x01, // Usage Page (Generic Desktop)              0
+            0x09, 0x02, // Usage (Mouse)                             2
+            0xa1, 0x01, // Collection (Application)                  4
+            0x19, 0x05, //   Usage Minimum (5)                       6
+            0x29, 0x03, //   Usage Maximum (3)                       8
+            0x15, 0x81, //   Logical Minimum (-127)                  10
+            0x25, 0xff, //   Logical Maximum (255)                   12
+            0x75, 0x08, //   Report Size (8)                         14
+            0x95, 0x02, //   Report Count (2)                        16
+            0x81, 0x02, //   Input (Data,Var,Abs)                    18
+            0x85, 0x02, //   Report ID (2)                           20
+            0x15, 0x00, //   Logical Minimum (0)                     22
+            0x81, 0x02, //   Input (Data,Var,Abs)                    24
+            0xa4, //         Push                                    26
+            0xc0, // End Collection                                  27
+        ];
+        assert_eq!(
+            messages(&bytes, &LintOptions::default()),
+            vec![
+                (Some(6), "Usage Minimum (0x5) is greater than the Usage Maximum (0x3)".to_string()),
+                (Some(12), "Logical Maximum (255) is only valid as unsigned but the Logical Minimum (-127) is negative, so it is interpreted as -1".to_string()),
+                (Some(18), "input item without a Report ID in a report descriptor that uses Report IDs".to_string()),
+                (Some(26), "Push without a matching Pop".to_string()),
+            ]
+        );
+
+        // Reserved item type (0b11) and a Pop without Push
+        let bytes = [0x0c, 0xb4];
+        assert_eq!(
+            messages(&bytes, &LintOptions::default()),
+            vec![
+                (Some(0), "Reserved item type in header 0x0c".to_string()),
+                (Some(1), "Pop without a matching Push".to_string()),
+            ]
+        );
+
+        // 4096 × 32 bits is one byte more than the kernel accepts
+        let bytes = [
+            0x05, 0x01, // Usage Page (Generic Desktop)              0
+            0x09, 0x00, // Usage (Undefined)                         2
+            0xa1, 0x01, // Collection (Application)                  4
+            0x75, 0x20, //   Report Size (32)                        6
+            0x96, 0x00, 0x10, // Report Count (4096)                 8
+            0x81, 0x01, //   Input (Cnst,Arr,Abs)                    11
+            0xc0, // End Collection                                  13
+        ];
+        assert_eq!(
+            messages(&bytes, &LintOptions::default()),
+            vec![(Some(11), "Report Size (32) × Report Count (4096) is 131072 bits, larger than the kernel's limit of 131064 bits per report".to_string())]
+        );
+    }
+}
@@ -0,0 +1,409 @@
+// SPDX-License-Identifier: MIT
+//
+// Reads the report descriptors from the output of `lsusb -v`, e.g.
+//
+// Bus 001 Device 004: ID 046d:c077 Logitech, Inc. Mouse
+// Device Descriptor:
+//   idVendor           0x046d Logitech, Inc.
+//   idProduct          0xc077 Mouse
+//   ...
+//           Report Descriptor: (length is 50)
+//             Item(Global): Usage Page, data= [ 0x01 ] 1
+//                             Generic Desktop Controls
+//             Item(Local ): Usage, data= [ 0x02 ] 2
+//                             Mouse
+//             ...
+//
+// lsusb only prints the report descriptors when run as root.
+
+use anyhow::{bail, Context, Result};
+use std::io::BufRead;
+use std::path::Path;
+
+use crate::{Backend, BpfOption, Event, EventNode, Outfile, ReportDescriptor, Styles};
+
+/// A report descriptor and the device it belongs to
+struct LsusbDescriptor {
+    name: String,
+    vid: u32,
+    pid: u32,
+    interface: Option<u8>,
+    rdesc: Vec<u8>,
+}
+
+pub struct LsusbBackend {
+    name: String,
+    vid: u32,
+    pid: u32,
+    rdesc: Vec<u8>,
+}
+
+/// The device descriptor fields collected so far
+#[derive(Default)]
+struct Device {
+    description: String,
+    manufacturer: Option<String>,
+    product: Option<String>,
+    vid: u32,
+    pid: u32,
+    interface: Option<u8>,
+}
+
+impl Device {
+    fn name(&self) -> String {
+        match (&self.manufacturer, &self.product) {
+            (Some(manufacturer), Some(product)) => format!("{manufacturer} {product}"),
+            (None, Some(product)) => product.clone(),
+            _ if !self.description.is_empty() => self.description.clone(),
+            _ => String::from("No Name"),
+        }
+    }
+}
+
+/// The tag of an item by its lsusb name
+fn item_tag(item_type: u8, name: &str) -> Option<u8> {
+    let tag = match (item_type, name) {
+        (0, "Input") => 0x8,
+        (0, "Output") => 0x9,
+        (0, "Collection") => 0xa,
+        (0, "Feature") => 0xb,
+        (0, "End Collection") => 0xc,
+        (1, "Usage Page") => 0x0,
+        (1, "Logical Minimum") => 0x1,
+        (1, "Logical Maximum") => 0x2,
+        (1, "Physical Minimum") => 0x3,
+        (1, "Physical Maximum") => 0x4,
+        (1, "Unit Exponent") => 0x5,
+        (1, "Unit") => 0x6,
+        (1, "Report Size") => 0x7,
+        (1, "Report ID") => 0x8,
+        (1, "Report Count") => 0x9,
+        (1, "Push") => 0xa,
+        (1, "Pop") => 0xb,
+        (2, "Usage") => 0x0,
+        (2, "Usage Minimum") => 0x1,
+        (2, "Usage Maximum") => 0x2,
+        (2, "Designator Index") => 0x3,
+        (2, "Designator Minimum") => 0x4,
+        (2, "Designator Maximum") => 0x5,
+        (2, "String Index") => 0x7,
+        (2, "String Minimum") => 0x8,
+        (2, "String Maximum") => 0x9,
+        (2, "Delimiter") => 0xa,
+        _ => return None,
+    };
+    Some(tag)
+}
+
+/// Convert an item line back into the item's bytes, e.g.
+/// `Item(Global): Logical Maximum, data= [ 0xff 0x00 ] 255` is `26 ff 00`
+fn parse_item(line: &str) -> Result<Vec<u8>> {
+    let parse = || -> Option<Vec<u8>> {
+        let (item_type, rest) = line.strip_prefix("Item(")?.split_once("):")?;
+        let item_type = match item_type.trim() {
+            "Main" => 0,
+            "Global" => 1,
+            "Local" => 2,
+            _ => return None,
+        };
+        let (name, data) = rest.split_once(", data=")?;
+        let tag = item_tag(item_type, name.trim())?;
+        let data = match data.trim() {
+            "none" => Vec::new(),
+            data => data
+                .strip_prefix('[')?
+                .split_once(']')?
+                .0
+                .split_whitespace()
+                .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16).ok())
+                .collect::<Option<Vec<u8>>>()?,
+        };
+        let size = match data.len() {
+            0 => 0,
+            1 => 1,
+            2 => 2,
+            4 => 3,
+            _ => return None,
+        };
+        Some([vec![(tag << 4) | (item_type << 2) | size], data].concat())
+    };
+    parse().context(format!("Unsupported item: {line}"))
+}
+
+/// The first word after the field name, and the rest of the line, e.g.
+/// `("2", "USB Receiver")` for `iProduct   2 USB Receiver`
+fn field_value(line: &str) -> (&str, &str) {
+    let value = line
+        .split_once(char::is_whitespace)
+        .map_or("", |(_, rest)| rest.trim());
+    value.split_once(' ').unwrap_or((value, ""))
+}
+
+/// Whether `data` looks like `lsusb -v` output, i.e. has an interface
+/// descriptor or a report descriptor section
+pub fn is_lsusb_output(data: &[u8]) -> bool {
+    String::from_utf8_lossy(data).lines().any(|l| {
+        let l = l.trim_start();
+        l.starts_with("bInterfaceNumber") || l.starts_with("Report Descriptor:")
+    })
+}
+
+impl LsusbBackend {
+    /// Read the `lsusb -v` output in `path`. If it contains several report
+    /// descriptors, use the one of the interface with this number or the
+    /// first one if `interface` is `None`.
+    pub fn from_path_and_interface(path: &Path, interface: Option<u8>) -> Result<Self> {
+        let f = std::fs::File::open(path)?;
+        let lines = std::io::BufReader::new(f).lines().map_while(Result::ok);
+
+        let mut descriptors: Vec<LsusbDescriptor> = Vec::new();
+        let mut device = Device::default();
+        // The indentation and expected length of the current Report Descriptor section
+        let mut section: Option<(usize, usize)> = None;
+
+        for line in lines {
+            let indent = line.len() - line.trim_start().len();
+            let line = line.trim();
+            if let Some((section_indent, length)) = section {
+                if indent > section_indent || line.is_empty() {
+                    if line.starts_with("Item(") {
+                        let bytes = parse_item(line)?;
+                        descriptors.last_mut().unwrap().rdesc.extend(bytes);
+                    }
+                    continue;
+                }
+                let rdesc = &descriptors.last().unwrap().rdesc;
+                if rdesc.len() != length {
+                    bail!(
+                        "Report descriptor length is {} but expected {length}",
+                        rdesc.len()
+                    );
+                }
+                section = None;
+            }
+
+            if let Some(rest) = line.strip_prefix("Bus ") {
+                if let Some((_, id)) = rest.split_once(": ID ") {
+                    let (_, description) = id.split_once(' ').unwrap_or((id, ""));
+                    device = Device {
+                        description: description.to_string(),
+                        ..Device::default()
+                    };
+                }
+                continue;
+            }
+            let Some(field) = line.split_whitespace().next() else {
+                continue;
+            };
+            match field {
+                "idVendor" | "idProduct" => {
+                    let (id, _) = field_value(line);
+                    let id = u32::from_str_radix(id.trim_start_matches("0x"), 16)
+                        .context(format!("Invalid {field}"))?;
+                    if field == "idVendor" {
+                        device.vid = id;
+                    } else {
+                        device.pid = id;
+                    }
+                }
+                "iManufacturer" | "iProduct" => {
+                    let (_, string) = field_value(line);
+                    let string = (!string.is_empty()).then(|| string.to_string());
+                    if field == "iManufacturer" {
+                        device.manufacturer = string;
+                    } else {
+                        device.product = string;
+                    }
+                }
+                "bInterfaceNumber" => device.interface = field_value(line).0.parse::<u8>().ok(),
+                "Report" => {
+                    if let Some(length) = line
+                        .strip_prefix("Report Descriptor: (length is ")
+                        .and_then(|l| l.strip_suffix(')'))
+                    {
+                        let length = length.parse::<usize>()?;
+                        descriptors.push(LsusbDescriptor {
+                            name: device.name(),
+                            vid: device.vid,
+                            pid: device.pid,
+                            interface: device.interface,
+                            rdesc: Vec::new(),
+                        });
+                        section = Some((indent, length));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some((_, length)) = section {
+            let rdesc = &descriptors.last().unwrap().rdesc;
+            if rdesc.len() != length {
+                bail!(
+                    "Report descriptor length is {} but expected {length}",
+                    rdesc.len()
+                );
+            }
+        }
+
+        let available = descriptors
+            .iter()
+            .filter_map(|d| d.interface.map(|i| format!("{i}")))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let descriptor = match interface {
+            Some(number) => match descriptors.iter().position(|d| d.interface == Some(number)) {
+                Some(idx) => descriptors.swap_remove(idx),
+                None => bail!(
+                    "No report descriptor for interface {number}, available interfaces: {available}"
+                ),
+            },
+            None if descriptors.len() > 1 => {
+                Outfile::new().write_comment_styled(
+                    Styles::Note,
+                    &format!(
+                        "Using the first of the report descriptors of interfaces {available}, select another one with --interface"
+                    ),
+                );
+                descriptors.swap_remove(0)
+            }
+            None if !descriptors.is_empty() => descriptors.swap_remove(0),
+            None => bail!("No report descriptor found, run lsusb -v as root"),
+        };
+
+        Ok(LsusbBackend {
+            name: descriptor.name,
+            vid: descriptor.vid,
+            pid: descriptor.pid,
+            rdesc: descriptor.rdesc,
+        })
+    }
+}
+
+impl TryFrom<&Path> for LsusbBackend {
+    type Error = anyhow::Error;
+
+    fn try_from(path: &Path) -> Result<Self> {
+        LsusbBackend::from_path_and_interface(path, None)
+    }
+}
+
+impl Backend for LsusbBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bustype(&self) -> u32 {
+        // lsusb only lists USB devices
+        0x03
+    }
+
+    fn vid(&self) -> u32 {
+        self.vid
+    }
+
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn rdesc(&self) -> &[u8] {
+        &self.rdesc
+    }
+
+    fn event_nodes(&self) -> &[EventNode] {
+        &[]
+    }
+
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        _rdesc: &ReportDescriptor,
+        _callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    const LSUSB: &str = "
+Bus 001 Device 004: ID 046d:c077 Logitech, Inc. Mouse
+Device Descriptor:
+  bLength                18
+  idVendor           0x046d Logitech, Inc.
+  idProduct          0xc077 Mouse
+  iManufacturer           1 Logitech
+  iProduct                2 USB Optical Mouse
+  Configuration Descriptor:
+    Interface Descriptor:
+      bInterfaceNumber        0
+      bInterfaceClass         3 Human Interface Device
+        HID Device Descriptor:
+          bDescriptorType        34 Report
+          wDescriptorLength      12
+          Report Descriptor: (length is 12)
+            Item(Global): Usage Page, data= [ 0x01 ] 1
+                            Generic Desktop Controls
+            Item(Local ): Usage, data= [ 0x02 ] 2
+                            Mouse
+            Item(Main  ): Collection, data= [ 0x01 ] 1
+                            Application
+            Item(Global): Logical Maximum, data= [ 0xff 0x00 ] 255
+            Item(Main  ): Input, data= [ 0x02 ] 2
+                            Data Variable Absolute No_Wrap Linear
+                            Preferred_State No_Null_Position Non_Volatile Bitfield
+            Item(Main  ): End Collection, data=none
+      Endpoint Descriptor:
+        bLength                 7
+    Interface Descriptor:
+      bInterfaceNumber        1
+        HID Device Descriptor:
+          Report Descriptor: (length is 2)
+            Item(Global): Usage Page, data= [ 0x0c ] 12
+                            Consumer
+Device Status:     0x0000
+  (Bus Powered)
+";
+
+    #[test]
+    fn test_lsusb() {
+        assert_eq!(
+            parse_item("Item(Global): Logical Minimum, data= [ 0x00 0x80 0xff 0xff ] -32768")
+                .unwrap(),
+            vec![0x17, 0x00, 0x80, 0xff, 0xff]
+        );
+        assert!(parse_item("Item(Global): Unknown, data= [ 0x00 ] 0").is_err());
+
+        assert!(is_lsusb_output(LSUSB.as_bytes()));
+        assert!(!is_lsusb_output(b"05 01 09 02"));
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(LSUSB.as_bytes()).unwrap();
+        file.flush().unwrap();
+
+        let mut backend = None;
+        let output = crate::tests::capture_output(|| {
+            backend = Some(LsusbBackend::try_from(file.path()).unwrap());
+        });
+        assert!(output.contains("report descriptors of interfaces 0, 1,"));
+        let backend = backend.unwrap();
+        assert_eq!(backend.name(), "Logitech USB Optical Mouse");
+        assert_eq!((backend.vid(), backend.pid()), (0x046d, 0xc077));
+        assert_eq!(
+            backend.rdesc(),
+            &[0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x26, 0xff, 0x00, 0x81, 0x02, 0xc0]
+        );
+
+        let backend = LsusbBackend::from_path_and_interface(file.path(), Some(1)).unwrap();
+        assert_eq!(backend.rdesc(), &[0x05, 0x0c]);
+        let missing = LsusbBackend::from_path_and_interface(file.path(), Some(2));
+        assert!(missing
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("available interfaces: 0, 1"));
+    }
+}
@@ -4,7 +4,7 @@
 use anyhow::Result;
 use std::path::Path;
 
-use crate::{Backend, BpfOption, EventNode, ReportDescriptor};
+use crate::{Backend, BpfOption, Event, EventNode, ReportDescriptor};
 
 #[derive(Debug)]
 pub struct NumberArrayBackend {
@@ -15,10 +15,139 @@ pub struct NumberArrayBackend {
     rdesc: Vec<u8>,
 }
 
-impl TryFrom<&Path> for NumberArrayBackend {
-    type Error = anyhow::Error;
+/// Remove `/* */` and `//` comments and preprocessor directives. Lines
+/// printed by `print_rdesc_items`, e.g. `# 🮥 0x05, 0x01, // Usage Page ...`,
+/// keep their bytes.
+fn strip_comments(data: &str) -> String {
+    let mut code = String::new();
+    let mut rest = data;
+    while let Some(start) = rest.find("/*") {
+        code.push_str(&rest[..start]);
+        code.push(' ');
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    code.push_str(rest);
+
+    code.lines()
+        .filter_map(|line| {
+            let line = line.split_once("//").map_or(line, |(code, _)| code).trim();
+            match line.strip_prefix('#') {
+                Some(listing) => {
+                    let listing =
+                        listing.trim_start_matches(|c: char| c.is_whitespace() || !c.is_ascii());
+                    listing
+                        .split([',', ' '])
+                        .filter(|s| !s.is_empty())
+                        .all(|s| s.starts_with("0x"))
+                        .then_some(listing)
+                }
+                None => Some(line),
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
 
-    fn try_from(path: &Path) -> Result<Self> {
+/// The C arrays in the source as tuples of name and initializer, e.g.
+/// `("rdesc", "0x05, 0x01")` for `static const __u8 rdesc[] = { 0x05, 0x01 };`
+fn c_arrays(source: &str) -> Vec<(&str, &str)> {
+    let mut arrays = Vec::new();
+    let mut rest = source;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            break;
+        };
+        let declaration = rest[..open].trim_end();
+        if let Some(declaration) = declaration
+            .strip_suffix('=')
+            .map(str::trim_end)
+            .and_then(|d| d.strip_suffix(']'))
+            .and_then(|d| d.rsplit_once('['))
+            .map(|(d, _)| d.trim_end())
+        {
+            let name = declaration
+                .rsplit(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .next()
+                .unwrap_or_default();
+            arrays.push((name, &rest[open + 1..close]));
+        }
+        rest = &rest[close + 1..];
+    }
+    arrays
+}
+
+/// Parse a C integer literal: hexadecimal with `0x`, octal with a leading
+/// `0`, decimal otherwise, each with an optional `u` suffix.
+fn parse_c_number(token: &str) -> Result<u8> {
+    let number = token.trim_end_matches(['u', 'U']);
+    let value = if let Some(hex) = number
+        .strip_prefix("0x")
+        .or_else(|| number.strip_prefix("0X"))
+    {
+        u32::from_str_radix(hex, 16)
+    } else if number.len() > 1 && number.starts_with('0') {
+        u32::from_str_radix(&number[1..], 8)
+    } else {
+        number.parse::<u32>()
+    }
+    .map_err(|e| anyhow::anyhow!("Failed to parse '{}' as number: {}", token, e))?;
+
+    u8::try_from(value).map_err(|_| anyhow::anyhow!("Value '{}' does not fit in a byte", token))
+}
+
+/// Parse a bare hex byte like `05` or `a1`
+fn parse_hex_number(token: &str) -> Result<u8> {
+    u8::from_str_radix(token, 16)
+        .map_err(|e| anyhow::anyhow!("Failed to parse '{}' as hex: {}", token, e))
+}
+
+/// Parse C or C++ source with the report descriptor as an array of
+/// integer literals. Where there are several arrays, `array` picks one by
+/// name, otherwise the first one is used. Without an array and without
+/// `0x` literals the numbers are bare hex bytes with comments, e.g.
+/// `05 01 // Usage Page (Generic Desktop)`.
+fn parse_c_source(data: &str, array: Option<&str>) -> Result<Vec<u8>> {
+    let source = strip_comments(data);
+    let arrays = c_arrays(&source);
+    let initializer = match (array, arrays.first()) {
+        (Some(name), _) => match arrays.iter().find(|(n, _)| *n == name) {
+            Some((_, initializer)) => *initializer,
+            None => anyhow::bail!(
+                "No array named '{}', available arrays: {}",
+                name,
+                arrays
+                    .iter()
+                    .map(|(n, _)| *n)
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ),
+        },
+        (None, Some((_, initializer))) => initializer,
+        // Just the numbers, possibly with braces
+        (None, None) => source.as_str(),
+    };
+
+    let is_c_literals = !arrays.is_empty() || source.contains("0x") || source.contains("0X");
+    let parse_number = if is_c_literals {
+        parse_c_number
+    } else {
+        parse_hex_number
+    };
+
+    initializer
+        .split(|c: char| c.is_whitespace() || matches!(c, ',' | '{' | '}' | ';'))
+        .filter(|s| !s.is_empty())
+        .map(parse_number)
+        .collect()
+}
+
+impl NumberArrayBackend {
+    /// Read the report descriptor from the number array in `path`. If it
+    /// contains several C arrays, pick the one called `array`.
+    pub fn from_path_and_array(path: &Path, array: Option<&str>) -> Result<Self> {
         let data: String = std::fs::read_to_string(path)?;
 
         // Formats supported:
@@ -28,42 +157,61 @@ impl TryFrom<&Path> for NumberArrayBackend {
         // [01, 02, 03, 04...]
         //
         // And all of the above as 0x01
-
-        let data = data.trim().trim_start_matches('[').trim_end_matches(']');
-        let has_separators = data.chars().any(|c| c.is_whitespace() || c == ',');
-
-        let rdesc = if has_separators {
-            data.split(|c: char| c.is_whitespace() || c == ',')
-                .filter(|s| !s.is_empty())
-                .map(|token| {
-                    let hex_str = token
-                        .trim()
-                        .strip_prefix("0x")
-                        .or_else(|| token.trim().strip_prefix("0X"))
-                        .unwrap_or(token.trim());
-
-                    u8::from_str_radix(hex_str, 16)
-                        .map_err(|e| anyhow::anyhow!("Failed to parse '{}' as hex: {}", token, e))
-                })
-                .collect::<Result<Vec<u8>>>()?
+        //
+        // C/C++ sources with comments, decimal and hex values, e.g.
+        // static const __u8 rdesc[] = {
+        //     0x05, 0x01,  /* Usage Page (Generic Desktop) */
+        //     9, 2,        // Usage (Mouse)
+        // };
+        // bare hex bytes with comments, e.g.
+        // 05 01  // Usage Page (Generic Desktop)
+        // and the report descriptor listing of hid-recorder itself.
+
+        let is_c_source = data
+            .chars()
+            .any(|c| matches!(c, '{' | '}' | '/' | '#' | '=' | ';'));
+
+        let rdesc = if is_c_source || array.is_some() {
+            parse_c_source(&data, array)?
         } else {
-            let hex_str = data
-                .strip_prefix("0x")
-                .or_else(|| data.strip_prefix("0X"))
-                .unwrap_or(data);
-
-            if hex_str.len() % 2 != 0 {
-                anyhow::bail!("Continuous hex string must have an even number of digits");
+            let data = data.trim().trim_start_matches('[').trim_end_matches(']');
+            let has_separators = data.chars().any(|c| c.is_whitespace() || c == ',');
+
+            if has_separators {
+                data.split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|s| !s.is_empty())
+                    .map(|token| {
+                        let hex_str = token
+                            .trim()
+                            .strip_prefix("0x")
+                            .or_else(|| token.trim().strip_prefix("0X"))
+                            .unwrap_or(token.trim());
+
+                        u8::from_str_radix(hex_str, 16).map_err(|e| {
+                            anyhow::anyhow!("Failed to parse '{}' as hex: {}", token, e)
+                        })
+                    })
+                    .collect::<Result<Vec<u8>>>()?
+            } else {
+                let hex_str = data
+                    .strip_prefix("0x")
+                    .or_else(|| data.strip_prefix("0X"))
+                    .unwrap_or(data);
+
+                if hex_str.len() % 2 != 0 {
+                    anyhow::bail!("Continuous hex string must have an even number of digits");
+                }
+
+                (0..hex_str.len())
+                    .step_by(2)
+                    .map(|i| {
+                        let pair = &hex_str[i..i + 2];
+                        u8::from_str_radix(pair, 16).map_err(|e| {
+                            anyhow::anyhow!("Failed to parse '{}' as hex: {}", pair, e)
+                        })
+                    })
+                    .collect::<Result<Vec<u8>>>()?
             }
-
-            (0..hex_str.len())
-                .step_by(2)
-                .map(|i| {
-                    let pair = &hex_str[i..i + 2];
-                    u8::from_str_radix(pair, 16)
-                        .map_err(|e| anyhow::anyhow!("Failed to parse '{}' as hex: {}", pair, e))
-                })
-                .collect::<Result<Vec<u8>>>()?
         };
 
         Ok(NumberArrayBackend {
@@ -76,6 +224,14 @@ impl TryFrom<&Path> for NumberArrayBackend {
     }
 }
 
+impl TryFrom<&Path> for NumberArrayBackend {
+    type Error = anyhow::Error;
+
+    fn try_from(path: &Path) -> Result<Self> {
+        NumberArrayBackend::from_path_and_array(path, None)
+    }
+}
+
 impl Backend for NumberArrayBackend {
     fn name(&self) -> &str {
         &self.name
@@ -101,7 +257,12 @@ impl Backend for NumberArrayBackend {
         &[]
     }
 
-    fn read_events(&self, _use_bpf: BpfOption, _rdesc: &ReportDescriptor) -> Result<()> {
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        _rdesc: &ReportDescriptor,
+        _callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
         Ok(())
     }
 }
@@ -404,4 +565,78 @@ mod tests {
         let continuous = parse_from_string("010203").unwrap();
         assert_eq!(continuous, vec![0x01, 0x02, 0x03]);
     }
+
+    #[test]
+    fn test_c_source() {
+        let result = parse_from_string(
+            "#include <linux/types.h>\n\
+             /* A mouse */\n\
+             static const __u8 rdesc[] = {\n\
+             \t0x05, 0x01, /* Usage Page (Generic Desktop) */\n\
+             \t9, 2,       // Usage (Mouse)\n\
+             \t0xa1, 001,\n\
+             \t0xc0,\n\
+             };\n",
+        )
+        .unwrap();
+        assert_eq!(result, vec![0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0xc0]);
+    }
+
+    #[test]
+    fn test_c_source_decimal_and_hex() {
+        let result = parse_from_string("{ 0x05, 1, 9, 0x02, 255u, }").unwrap();
+        assert_eq!(result, vec![0x05, 0x01, 0x09, 0x02, 0xff]);
+    }
+
+    #[test]
+    fn test_c_source_value_too_large() {
+        let result = parse_from_string("{ 0x05, 256 }");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_c_source_multiple_arrays() {
+        let source = "static const __u8 original[] = { 0x05, 0x01 };\n\
+                      static const __u8 fixed_rdesc[2] = { 0x05, 0x0d };\n\
+                      HID_BPF_OPS(fix) = { .hid_rdesc_fixup = (void *)fix };\n";
+        let file = create_temp_file_with_content(source);
+        let first = NumberArrayBackend::try_from(file.path()).unwrap();
+        assert_eq!(first.rdesc(), &[0x05, 0x01]);
+
+        let fixed =
+            NumberArrayBackend::from_path_and_array(file.path(), Some("fixed_rdesc")).unwrap();
+        assert_eq!(fixed.rdesc(), &[0x05, 0x0d]);
+
+        let missing = NumberArrayBackend::from_path_and_array(file.path(), Some("fix"));
+        assert!(missing
+            .unwrap_err()
+            .to_string()
+            .contains("available arrays: original, fixed_rdesc"));
+    }
+
+    #[test]
+    fn test_annotated_bare_hex() {
+        let result = parse_from_string(
+            "# Mouse\n\
+             05 01 // Usage Page (Generic Desktop)\n\
+             09 02 // Usage (Mouse)\n\
+             a1 01 /* Collection (Application) */\n\
+             c0\n",
+        )
+        .unwrap();
+        assert_eq!(result, vec![0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0xc0]);
+    }
+
+    #[test]
+    fn test_rdesc_listing() {
+        let result = parse_from_string(
+            "#   Bytes                          // Field Name                              Offset\n\
+             # ----------------------------------------------------------------------------------\n\
+             # 🮥 0x05, 0x01,                    // Usage Page (Generic Desktop)            0\n\
+             # 🭬 0x09, 0x02,                    // Usage (Mouse)                           2\n\
+             # ┇ 0x81, 0x02,                    // Input (Data,Var,Abs)                    4\n",
+        )
+        .unwrap();
+        assert_eq!(result, vec![0x05, 0x01, 0x09, 0x02, 0x81, 0x02]);
+    }
 }
@@ -0,0 +1,401 @@
+// SPDX-License-Identifier: MIT
+//
+// Writes the events of a device or recording as pcapng file with the
+// LINKTYPE_USB_LINUX_MMAPPED link type, i.e. as if they were captured
+// by usbmon.
+//
+// Wireshark's USB HID dissector only decodes the reports if it has seen
+// the device's enumeration, so the capture starts with a synthetic
+// GET_DESCRIPTOR exchange for the device, configuration, product string
+// and report descriptor. The device is always device 1 on bus 1 with a
+// single HID interface and its interrupt endpoints 0x81 (IN) and
+// 0x01 (OUT). Input reports are interrupt IN transfers, output reports
+// interrupt OUT transfers and feature reports GET_REPORT requests.
+//
+// Timestamps start at the Unix epoch, Wireshark shows the time relative to
+// the first packet by default anyway.
+
+use anyhow::Result;
+use hidreport::{Report, ReportDescriptor};
+use std::io::Write;
+use std::time::Duration;
+
+use crate::{Backend, BpfOption, Event, ReportDirection};
+
+const LINKTYPE_USB_LINUX_MMAPPED: u16 = 220;
+
+const BUS: u16 = 1;
+const DEVICE: u8 = 1;
+const ENDPOINT_IN: u8 = 0x81;
+const ENDPOINT_OUT: u8 = 0x01;
+/// The string descriptor index of the device name
+const PRODUCT_STRING: u8 = 2;
+/// -EINPROGRESS, the status of a submitted URB
+const EINPROGRESS: i32 = -115;
+
+#[derive(Clone, Copy)]
+enum TransferType {
+    Interrupt = 1,
+    Control = 2,
+}
+
+/// A pcapng block with its type, body and trailing length
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let padding = (4 - body.len() % 4) % 4;
+    let len = (12 + body.len() + padding) as u32;
+    let mut block = Vec::with_capacity(len as usize);
+    block.extend(block_type.to_le_bytes());
+    block.extend(len.to_le_bytes());
+    block.extend(body);
+    block.extend(vec![0u8; padding]);
+    block.extend(len.to_le_bytes());
+    block
+}
+
+/// A pcapng option with its code, value and padding
+fn option(code: u16, value: &[u8]) -> Vec<u8> {
+    let padding = (4 - value.len() % 4) % 4;
+    let mut option = Vec::new();
+    option.extend(code.to_le_bytes());
+    option.extend((value.len() as u16).to_le_bytes());
+    option.extend(value);
+    option.extend(vec![0u8; padding]);
+    option
+}
+
+/// The Section Header Block and the Interface Description Block
+fn header(name: &str) -> Vec<u8> {
+    let mut shb = Vec::new();
+    shb.extend(0x1a2b3c4du32.to_le_bytes());
+    shb.extend(1u16.to_le_bytes()); // major version
+    shb.extend(0u16.to_le_bytes()); // minor version
+    shb.extend((-1i64).to_le_bytes()); // unknown section length
+    shb.extend(option(4, b"hid-recorder")); // shb_userappl
+    shb.extend(option(0, &[])); // opt_endofopt
+
+    let mut idb = Vec::new();
+    idb.extend(LINKTYPE_USB_LINUX_MMAPPED.to_le_bytes());
+    idb.extend(0u16.to_le_bytes()); // reserved
+    idb.extend(0u32.to_le_bytes()); // no snaplen limit
+    idb.extend(option(2, b"usbmon1")); // if_name
+    idb.extend(option(3, name.as_bytes())); // if_description
+    idb.extend(option(0, &[])); // opt_endofopt
+
+    [block(0x0a0d0d0a, &shb), block(0x00000001, &idb)].concat()
+}
+
+/// One usbmon event, i.e. the submission (`S`) or completion (`C`) of an
+/// URB, with the 64 byte header of LINKTYPE_USB_LINUX_MMAPPED
+struct Urb<'a> {
+    id: u64,
+    event: u8,
+    transfer_type: TransferType,
+    endpoint: u8,
+    status: i32,
+    /// The requested length for a submission, the transferred length
+    /// for a completion
+    length: usize,
+    setup: Option<[u8; 8]>,
+    data: &'a [u8],
+}
+
+impl Urb<'_> {
+    /// The Enhanced Packet Block for this URB at this timestamp
+    fn to_block(&self, timestamp: Duration) -> Vec<u8> {
+        let mut packet = vec![0u8; 64];
+        packet[0..8].copy_from_slice(&self.id.to_le_bytes());
+        packet[8] = self.event;
+        packet[9] = self.transfer_type as u8;
+        packet[10] = self.endpoint;
+        packet[11] = DEVICE;
+        packet[12..14].copy_from_slice(&BUS.to_le_bytes());
+        packet[14] = if self.setup.is_some() { 0 } else { b'-' };
+        packet[15] = match (self.data.is_empty(), self.endpoint & 0x80 != 0) {
+            (false, _) => 0,
+            (true, true) => b'<',
+            (true, false) => b'>',
+        };
+        packet[16..24].copy_from_slice(&timestamp.as_secs().to_le_bytes());
+        packet[24..28].copy_from_slice(&timestamp.subsec_micros().to_le_bytes());
+        packet[28..32].copy_from_slice(&self.status.to_le_bytes());
+        packet[32..36].copy_from_slice(&(self.length as u32).to_le_bytes());
+        packet[36..40].copy_from_slice(&(self.data.len() as u32).to_le_bytes());
+        if let Some(setup) = self.setup {
+            packet[40..48].copy_from_slice(&setup);
+        }
+        if let TransferType::Interrupt = self.transfer_type {
+            packet[48..52].copy_from_slice(&1i32.to_le_bytes()); // interval
+        }
+        packet.extend(self.data);
+
+        let usecs = timestamp.as_micros() as u64;
+        let mut epb = Vec::new();
+        epb.extend(0u32.to_le_bytes()); // interface
+        epb.extend(((usecs >> 32) as u32).to_le_bytes());
+        epb.extend((usecs as u32).to_le_bytes());
+        epb.extend((packet.len() as u32).to_le_bytes());
+        epb.extend((packet.len() as u32).to_le_bytes());
+        epb.extend(packet);
+        block(0x00000006, &epb)
+    }
+}
+
+/// Writes the URBs with increasing URB ids
+struct UrbWriter<'a, W: Write> {
+    output: &'a mut W,
+    next_id: u64,
+}
+
+impl<W: Write> UrbWriter<'_, W> {
+    /// A control transfer with this setup packet, reading `data`
+    fn control_in(&mut self, setup: [u8; 8], data: &[u8], timestamp: Duration) -> Result<()> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let length = usize::from(u16::from_le_bytes([setup[6], setup[7]]));
+        let submission = Urb {
+            id,
+            event: b'S',
+            transfer_type: TransferType::Control,
+            endpoint: 0x80,
+            status: EINPROGRESS,
+            length,
+            setup: Some(setup),
+            data: &[],
+        };
+        let completion = Urb {
+            event: b'C',
+            status: 0,
+            length: data.len(),
+            setup: None,
+            data,
+            ..submission
+        };
+        self.output.write_all(&submission.to_block(timestamp))?;
+        self.output.write_all(&completion.to_block(timestamp))?;
+        Ok(())
+    }
+
+    /// An interrupt transfer on this endpoint. IN transfers have their
+    /// data in the completion, OUT transfers in the submission.
+    fn interrupt(&mut self, endpoint: u8, data: &[u8], timestamp: Duration) -> Result<()> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let is_in = endpoint & 0x80 != 0;
+        let submission = Urb {
+            id,
+            event: b'S',
+            transfer_type: TransferType::Interrupt,
+            endpoint,
+            status: EINPROGRESS,
+            length: data.len(),
+            setup: None,
+            data: if is_in { &[] } else { data },
+        };
+        let completion = Urb {
+            event: b'C',
+            status: 0,
+            data: if is_in { data } else { &[] },
+            ..submission
+        };
+        self.output.write_all(&submission.to_block(timestamp))?;
+        self.output.write_all(&completion.to_block(timestamp))?;
+        Ok(())
+    }
+}
+
+/// The setup packet of a GET_DESCRIPTOR request
+fn get_descriptor(request_type: u8, dtype: u8, index: u8, windex: u16, len: usize) -> [u8; 8] {
+    let mut setup = [request_type, 0x06, index, dtype, 0, 0, 0, 0];
+    setup[4..6].copy_from_slice(&windex.to_le_bytes());
+    setup[6..8].copy_from_slice(&(len as u16).to_le_bytes());
+    setup
+}
+
+/// The device descriptor
+fn device_descriptor(vid: u16, pid: u16) -> Vec<u8> {
+    let mut desc = vec![18, 0x01, 0x00, 0x02, 0, 0, 0, 64];
+    desc.extend(vid.to_le_bytes());
+    desc.extend(pid.to_le_bytes());
+    desc.extend([0x00, 0x01]); // bcdDevice
+    desc.extend([0, PRODUCT_STRING, 0, 1]);
+    desc
+}
+
+/// The configuration descriptor with a single HID interface and its
+/// interrupt endpoints
+fn config_descriptor(rdesc_len: usize, max_input: usize, max_output: Option<usize>) -> Vec<u8> {
+    let endpoint = |address: u8, size: usize| {
+        let mut desc = vec![7, 0x05, address, 0x03];
+        desc.extend((size.min(1024) as u16).to_le_bytes());
+        desc.push(1); // bInterval
+        desc
+    };
+    let mut hid = vec![9, 0x21, 0x11, 0x01, 0, 1, 0x22];
+    hid.extend((rdesc_len as u16).to_le_bytes());
+
+    let mut endpoints = endpoint(ENDPOINT_IN, max_input);
+    if let Some(max_output) = max_output {
+        endpoints.extend(endpoint(ENDPOINT_OUT, max_output));
+    }
+    let num_endpoints = 1 + u8::from(max_output.is_some());
+    let interface = vec![9, 0x04, 0, 0, num_endpoints, 0x03, 0, 0, 0];
+
+    let total_len = 9 + interface.len() + hid.len() + endpoints.len();
+    let mut desc = vec![9, 0x02];
+    desc.extend((total_len as u16).to_le_bytes());
+    desc.extend([1, 1, 0, 0x80, 50]);
+    [desc, interface, hid, endpoints].concat()
+}
+
+/// The string descriptor for this string
+fn string_descriptor(s: &str) -> Vec<u8> {
+    let utf16: Vec<u8> = s
+        .encode_utf16()
+        .take(126)
+        .flat_map(|c| c.to_le_bytes())
+        .collect();
+    [vec![(2 + utf16.len()) as u8, 0x03], utf16].concat()
+}
+
+/// Write the events of `backend` as pcapng to `output`, starting with a
+/// synthetic enumeration of the device. For a live device this only
+/// returns on error.
+pub fn write_pcapng(backend: &impl Backend, output: &mut impl Write) -> Result<()> {
+    let rdesc_bytes = backend.rdesc();
+    let rdesc = ReportDescriptor::try_from(rdesc_bytes)?;
+    let max_input = rdesc
+        .input_reports()
+        .iter()
+        .map(|r| r.size_in_bytes())
+        .max()
+        .unwrap_or(8);
+    let max_output = rdesc
+        .output_reports()
+        .iter()
+        .map(|r| r.size_in_bytes())
+        .max();
+
+    output.write_all(&header(backend.name()))?;
+    let mut writer = UrbWriter { output, next_id: 1 };
+
+    let device = device_descriptor(backend.vid() as u16, backend.pid() as u16);
+    let config = config_descriptor(rdesc_bytes.len(), max_input, max_output);
+    let product = string_descriptor(backend.name());
+    let t = Duration::ZERO;
+    writer.control_in(get_descriptor(0x80, 0x01, 0, 0, device.len()), &device, t)?;
+    writer.control_in(get_descriptor(0x80, 0x02, 0, 0, config.len()), &config, t)?;
+    writer.control_in(
+        get_descriptor(0x80, 0x03, PRODUCT_STRING, 0x0409, 255),
+        &product,
+        t,
+    )?;
+    writer.control_in(
+        get_descriptor(0x81, 0x22, 0, 0, rdesc_bytes.len()),
+        rdesc_bytes,
+        t,
+    )?;
+
+    backend.read_events(BpfOption::Never, &rdesc, &mut |event| {
+        let Event::Report(e) = event else {
+            return Ok(());
+        };
+        match e.direction {
+            ReportDirection::Input => writer.interrupt(ENDPOINT_IN, &e.bytes, e.elapsed)?,
+            ReportDirection::Output => writer.interrupt(ENDPOINT_OUT, &e.bytes, e.elapsed)?,
+            ReportDirection::Feature => {
+                // GET_REPORT(Feature) on the interface
+                let report_id = e.report_id.map_or(0, u8::from);
+                let mut setup = [0xa1, 0x01, report_id, 0x03, 0, 0, 0, 0];
+                setup[6..8].copy_from_slice(&(e.bytes.len() as u16).to_le_bytes());
+                writer.control_in(setup, &e.bytes, e.elapsed)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usbmon::UsbmonBackend;
+    use crate::{EventNode, ReportEvent};
+
+    // The descriptor that Mouse writes into the synthetic enumeration
+    const MOUSE: [u8; 50] = [
+        0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29,
+        0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05,
+        0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f, 0x75, 0x08, 0x95,
+        0x02, 0x81, 0x06, 0xc0, 0xc0,
+    ];
+
+    struct Mouse;
+
+    impl Backend for Mouse {
+        fn name(&self) -> &str {
+            "Test Mouse"
+        }
+        fn bustype(&self) -> u32 {
+            0x03
+        }
+        fn vid(&self) -> u32 {
+            0x046d
+        }
+        fn pid(&self) -> u32 {
+            0xc077
+        }
+        fn rdesc(&self) -> &[u8] {
+            &MOUSE
+        }
+        fn event_nodes(&self) -> &[EventNode] {
+            &[]
+        }
+        fn read_events(
+            &self,
+            _use_bpf: BpfOption,
+            rdesc: &ReportDescriptor,
+            callback: &mut dyn FnMut(Event) -> Result<()>,
+        ) -> Result<()> {
+            for (bytes, ms) in [([0x01, 0xff, 0x02], 0), ([0x00, 0x05, 0x00], 1500)] {
+                callback(Event::Report(ReportEvent::new(
+                    &bytes,
+                    ReportDirection::Input,
+                    rdesc,
+                    Duration::from_millis(ms),
+                    None,
+                )))?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pcapng_roundtrip() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write_pcapng(&Mouse, &mut file).unwrap();
+        file.flush().unwrap();
+
+        // usbmon reads the device from the enumeration
+        let backend = UsbmonBackend::try_from(file.path()).unwrap();
+        assert_eq!(backend.name(), "Test Mouse");
+        assert_eq!((backend.vid(), backend.pid()), (0x046d, 0xc077));
+        assert_eq!(backend.rdesc(), &MOUSE);
+
+        let rdesc = ReportDescriptor::try_from(backend.rdesc()).unwrap();
+        let mut events = Vec::new();
+        backend
+            .read_events(BpfOption::Never, &rdesc, &mut |event| {
+                if let Event::Report(e) = event {
+                    events.push((e.elapsed, e.bytes));
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                (Duration::ZERO, vec![0x01, 0xff, 0x02]),
+                (Duration::from_millis(1500), vec![0x00, 0x05, 0x00])
+            ]
+        );
+    }
+}
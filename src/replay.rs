@@ -0,0 +1,355 @@
+// SPDX-License-Identifier: MIT
+//
+// Replay or emulate a recording through a uhid device, similar to
+// hid-tools' hid-replay
+
+use anyhow::{bail, Context, Result};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use std::time::{Duration, Instant};
+
+use crate::uhid::{UhidDevice, UhidEvent, UhidReportType};
+use crate::{
+    print_report_event_description, print_unmatched_report_warning, Backend, BpfOption, Event,
+    Options, Outfile, ReportDirection, ReportEvent,
+};
+use hidreport::{Report, ReportDescriptor};
+
+pub struct ReplayOptions {
+    /// Replay the events at this multiple of the recorded speed
+    pub speed: f64,
+    /// Restart from the first event once the last one was replayed
+    pub repeat: bool,
+    /// Skip all events before this timestamp
+    pub start: Option<Duration>,
+    /// Skip all events after this timestamp
+    pub stop: Option<Duration>,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            speed: 1.0,
+            repeat: false,
+            start: None,
+            stop: None,
+        }
+    }
+}
+
+/// A recorded event and the delay relative to the first replayed event
+struct ReplayEvent {
+    delay: Duration,
+    bytes: Vec<u8>,
+}
+
+fn collect_events(backend: &impl Backend, opts: &ReplayOptions) -> Result<Vec<ReplayEvent>> {
+    if !(opts.speed.is_finite() && opts.speed > 0.0) {
+        bail!("Invalid speed factor {}", opts.speed);
+    }
+
+    let rdesc = ReportDescriptor::try_from(backend.rdesc())?;
+    let start = opts.start.unwrap_or(Duration::ZERO);
+    let stop = opts.stop.unwrap_or(Duration::MAX);
+
+    let mut events: Vec<(Duration, Vec<u8>)> = Vec::new();
+    backend.read_events(BpfOption::Never, &rdesc, &mut |event| {
+        if let Event::Report(e) = event {
+            if e.elapsed >= start && e.elapsed <= stop {
+                events.push((e.elapsed, e.bytes));
+            }
+        }
+        Ok(())
+    })?;
+
+    let Some(first) = events.first().map(|(elapsed, _)| *elapsed) else {
+        bail!("No events to replay");
+    };
+
+    events
+        .into_iter()
+        .map(|(elapsed, bytes)| {
+            let delay = (elapsed - first).as_secs_f64() / opts.speed;
+            Ok(ReplayEvent {
+                delay: Duration::try_from_secs_f64(delay)
+                    .with_context(|| format!("Invalid speed factor {}", opts.speed))?,
+                bytes,
+            })
+        })
+        .collect()
+}
+
+fn bytes_to_str(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// A uhid device that answers the kernel's GET_REPORT requests from the
+/// recorded feature reports and logs the other requests it receives.
+struct EmulatedDevice {
+    device: UhidDevice,
+    rdesc: ReportDescriptor,
+    uses_report_ids: bool,
+    feature_reports: Vec<Vec<u8>>,
+}
+
+impl EmulatedDevice {
+    fn create(backend: &impl Backend) -> Result<Self> {
+        let rdesc = ReportDescriptor::try_from(backend.rdesc())?;
+        let uses_report_ids = rdesc
+            .input_reports()
+            .iter()
+            .any(|r| r.report_id().is_some())
+            || rdesc
+                .output_reports()
+                .iter()
+                .any(|r| r.report_id().is_some())
+            || rdesc
+                .feature_reports()
+                .iter()
+                .any(|r| r.report_id().is_some());
+        let device = UhidDevice::create(
+            backend.name(),
+            backend.bustype() as u16,
+            backend.vid(),
+            backend.pid(),
+            backend.rdesc(),
+        )?;
+        Outfile::new().write_comment(&format!("Created uhid device '{}'", backend.name()));
+
+        Ok(EmulatedDevice {
+            device,
+            rdesc,
+            uses_report_ids,
+            feature_reports: backend.feature_reports().to_vec(),
+        })
+    }
+
+    fn find_feature_report(&mut self, rnum: u8) -> Option<&mut Vec<u8>> {
+        if self.uses_report_ids {
+            self.feature_reports
+                .iter_mut()
+                .find(|f| f.first() == Some(&rnum))
+        } else {
+            self.feature_reports.first_mut()
+        }
+    }
+
+    /// Strip the report number the kernel prepends to output and feature
+    /// reports where the device does not use report IDs
+    fn report_bytes(&self, data: &[u8]) -> Vec<u8> {
+        if self.uses_report_ids {
+            data.to_vec()
+        } else {
+            data.iter().skip(1).copied().collect()
+        }
+    }
+
+    /// Print the decoded fields of a report the kernel sent to us
+    fn print_report(&self, rtype: UhidReportType, data: &[u8]) {
+        let direction = match rtype {
+            UhidReportType::Input => ReportDirection::Input,
+            UhidReportType::Output => ReportDirection::Output,
+            UhidReportType::Feature => ReportDirection::Feature,
+        };
+        let event = ReportEvent::new(data, direction, &self.rdesc, Duration::ZERO, None);
+        if event.is_matched() {
+            let _ = print_report_event_description(&event, &self.rdesc, &Options::default());
+        } else {
+            print_unmatched_report_warning(&event);
+        }
+    }
+
+    fn handle_event(&mut self, event: &UhidEvent) -> Result<()> {
+        match event {
+            UhidEvent::Stop => bail!("The uhid device was stopped by the kernel"),
+            UhidEvent::Output { rtype, data } => {
+                Outfile::new().write_comment(&format!(
+                    "{rtype:?} report received: {}",
+                    bytes_to_str(data)
+                ));
+                self.print_report(*rtype, &self.report_bytes(data));
+            }
+            UhidEvent::GetReport {
+                id,
+                rnum,
+                rtype: UhidReportType::Feature,
+            } => {
+                let uses_report_ids = self.uses_report_ids;
+                match self.find_feature_report(*rnum) {
+                    Some(f) => {
+                        // The kernel expects the report number as first byte,
+                        // even where the device does not use report IDs
+                        let data = if uses_report_ids {
+                            f.clone()
+                        } else {
+                            [&[0], f.as_slice()].concat()
+                        };
+                        self.device.get_report_reply(*id, 0, &data)?;
+                        Outfile::new().write_comment(&format!(
+                            "GET_REPORT for feature report {rnum} answered with: {}",
+                            bytes_to_str(&data)
+                        ));
+                    }
+                    None => {
+                        self.device.get_report_reply(*id, libc::EIO as u16, &[])?;
+                        Outfile::new().write_comment(&format!(
+                            "GET_REPORT for feature report {rnum}: no recorded data"
+                        ));
+                    }
+                }
+            }
+            UhidEvent::GetReport { id, rnum, rtype } => {
+                self.device.get_report_reply(*id, libc::EIO as u16, &[])?;
+                Outfile::new().write_comment(&format!(
+                    "GET_REPORT for {rtype:?} report {rnum}: not supported"
+                ));
+            }
+            UhidEvent::SetReport {
+                id,
+                rnum,
+                rtype,
+                data,
+            } => {
+                Outfile::new().write_comment(&format!(
+                    "SET_REPORT for {rtype:?} report {rnum}: {}",
+                    bytes_to_str(data)
+                ));
+                let data = self.report_bytes(data);
+                self.print_report(*rtype, &data);
+                // Remember the new feature report contents so a subsequent
+                // GET_REPORT returns what the kernel set.
+                if *rtype == UhidReportType::Feature {
+                    match self.find_feature_report(*rnum) {
+                        Some(f) => *f = data,
+                        None => self.feature_reports.push(data),
+                    }
+                }
+                self.device.set_report_reply(*id, 0)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Process the events the kernel sends to our device until the deadline
+    /// passes, or until the next event if there is no deadline.
+    fn wait_until(&mut self, deadline: Option<Instant>) -> Result<Option<UhidEvent>> {
+        loop {
+            let timeout = match deadline {
+                None => PollTimeout::NONE,
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    // Round up so we don't spin for the last sub-millisecond
+                    let remaining = deadline - now + Duration::from_micros(999);
+                    PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX)
+                }
+            };
+            let mut pollfds = [PollFd::new(self.device.as_fd(), PollFlags::POLLIN)];
+            if poll(&mut pollfds, timeout)? > 0 {
+                let event = self.device.read_event()?;
+                self.handle_event(&event)?;
+                if deadline.is_none() {
+                    return Ok(Some(event));
+                }
+            }
+        }
+    }
+}
+
+/// Create a uhid device from the backend's name, ids and report descriptor
+/// and replay its events with the recorded timing.
+pub fn replay(backend: &impl Backend, opts: &ReplayOptions) -> Result<()> {
+    let events = collect_events(backend, opts)?;
+    let mut device = EmulatedDevice::create(backend)?;
+
+    // Wait until something (the kernel's hid-input, a hidraw reader, ...)
+    // opens the device, events before that would be lost
+    Outfile::new().write_comment("Waiting for the device to be opened");
+    while device.wait_until(None)? != Some(UhidEvent::Open) {}
+
+    loop {
+        Outfile::new().write_comment(&format!("Replaying {} events", events.len()));
+        let start_time = Instant::now();
+        for e in events.iter() {
+            device.wait_until(Some(start_time + e.delay))?;
+            device.device.input(&e.bytes)?;
+        }
+        if !opts.repeat {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Create a uhid device from the backend's name, ids and report descriptor
+/// and answer the kernel's requests until the process is terminated.
+pub fn emulate(backend: &impl Backend) -> Result<()> {
+    let mut device = EmulatedDevice::create(backend)?;
+    Outfile::new().write_comment("Emulating the device, press Ctrl+C to exit");
+    loop {
+        device.wait_until(None)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hidrecording::HidRecorderBackend;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_collect_events() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(
+            b"N: Test Mouse
+I: 3 1234 5678
+R: 50 05 01 09 02 a1 01 09 01 a1 00 05 09 19 01 29 03 15 00 25 01 95 03 75 01 81 02 95 01 75 05 81 01 05 01 09 30 09 31 15 81 25 7f 75 08 95 02 81 06 c0 c0
+E: 000000.000000 3 00 01 00
+E: 000001.000000 3 00 02 00
+E: 000002.000000 3 00 03 00
+E: 000004.000000 3 00 04 00
+",
+        )
+        .unwrap();
+        file.flush().unwrap();
+        let backend = HidRecorderBackend::try_from(file.path()).unwrap();
+
+        let events = collect_events(&backend, &ReplayOptions::default()).unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[3].delay, Duration::from_secs(4));
+
+        let opts = ReplayOptions {
+            speed: 2.0,
+            start: Some(Duration::from_secs(1)),
+            stop: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let events = collect_events(&backend, &opts).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].bytes, vec![0x00, 0x02, 0x00]);
+        assert_eq!(events[0].delay, Duration::ZERO);
+        assert_eq!(events[1].delay, Duration::from_millis(500));
+
+        let opts = ReplayOptions {
+            start: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        assert!(collect_events(&backend, &opts).is_err());
+
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e-300] {
+            let opts = ReplayOptions {
+                speed,
+                ..Default::default()
+            };
+            assert!(collect_events(&backend, &opts).is_err());
+        }
+    }
+}
@@ -0,0 +1,358 @@
+// SPDX-License-Identifier: MIT
+//
+// A minimal wrapper around /dev/uhid, see linux/uhid.h. The struct
+// uhid_event is packed so we (de)serialize it by hand.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::fs::OpenOptionsExt;
+
+const UHID_DESTROY: u32 = 1;
+const UHID_START: u32 = 2;
+const UHID_STOP: u32 = 3;
+const UHID_OPEN: u32 = 4;
+const UHID_CLOSE: u32 = 5;
+const UHID_OUTPUT: u32 = 6;
+const UHID_GET_REPORT: u32 = 9;
+const UHID_GET_REPORT_REPLY: u32 = 10;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_SET_REPORT: u32 = 13;
+const UHID_SET_REPORT_REPLY: u32 = 14;
+
+/// HID_MAX_DESCRIPTOR_SIZE
+const MAX_RDESC_SIZE: usize = 4096;
+/// UHID_DATA_MAX
+const MAX_DATA_SIZE: usize = 4096;
+/// sizeof(struct uhid_event), the largest member being struct uhid_create2_req
+const UHID_EVENT_SIZE: usize = 4376;
+
+// struct uhid_create2_req {
+// 	__u8 name[128];
+// 	__u8 phys[64];
+// 	__u8 uniq[64];
+// 	__u16 rd_size;
+// 	__u16 bus;
+// 	__u32 vendor;
+// 	__u32 product;
+// 	__u32 version;
+// 	__u32 country;
+// 	__u8 rd_data[HID_MAX_DESCRIPTOR_SIZE];
+// } __attribute__((__packed__));
+//
+// All offsets include the leading __u32 type of struct uhid_event
+const CREATE2_NAME: usize = 4;
+const CREATE2_RD_SIZE: usize = 260;
+const CREATE2_BUS: usize = 262;
+const CREATE2_VENDOR: usize = 264;
+const CREATE2_PRODUCT: usize = 268;
+const CREATE2_RD_DATA: usize = 280;
+
+/// The type of report in a [UhidEvent::Output], [UhidEvent::GetReport]
+/// or [UhidEvent::SetReport]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UhidReportType {
+    Feature,
+    Output,
+    Input,
+}
+
+impl TryFrom<u8> for UhidReportType {
+    type Error = anyhow::Error;
+
+    fn try_from(rtype: u8) -> Result<Self> {
+        match rtype {
+            0 => Ok(UhidReportType::Feature),
+            1 => Ok(UhidReportType::Output),
+            2 => Ok(UhidReportType::Input),
+            _ => bail!("Invalid uhid report type {rtype}"),
+        }
+    }
+}
+
+/// An event sent by the kernel to our uhid device
+#[derive(Debug, Clone, PartialEq)]
+pub enum UhidEvent {
+    Start,
+    Stop,
+    Open,
+    Close,
+    Output {
+        rtype: UhidReportType,
+        data: Vec<u8>,
+    },
+    /// A request for the report with the given report number, to be
+    /// answered with [UhidDevice::get_report_reply]
+    GetReport {
+        id: u32,
+        rnum: u8,
+        rtype: UhidReportType,
+    },
+    /// A request to set the report with the given report number, to be
+    /// answered with [UhidDevice::set_report_reply]
+    SetReport {
+        id: u32,
+        rnum: u8,
+        rtype: UhidReportType,
+        data: Vec<u8>,
+    },
+    /// Any event we do not handle
+    Other(u32),
+}
+
+fn create2_event(name: &str, bustype: u16, vid: u32, pid: u32, rdesc: &[u8]) -> Result<Vec<u8>> {
+    if rdesc.len() > MAX_RDESC_SIZE {
+        bail!("Report descriptor too long: {} bytes", rdesc.len());
+    }
+    let mut buf = vec![0u8; UHID_EVENT_SIZE];
+    buf[0..4].copy_from_slice(&UHID_CREATE2.to_ne_bytes());
+    // name must be NUL-terminated
+    let name = name.as_bytes();
+    let len = name.len().min(127);
+    buf[CREATE2_NAME..CREATE2_NAME + len].copy_from_slice(&name[..len]);
+    buf[CREATE2_RD_SIZE..CREATE2_RD_SIZE + 2].copy_from_slice(&(rdesc.len() as u16).to_ne_bytes());
+    buf[CREATE2_BUS..CREATE2_BUS + 2].copy_from_slice(&bustype.to_ne_bytes());
+    buf[CREATE2_VENDOR..CREATE2_VENDOR + 4].copy_from_slice(&vid.to_ne_bytes());
+    buf[CREATE2_PRODUCT..CREATE2_PRODUCT + 4].copy_from_slice(&pid.to_ne_bytes());
+    buf[CREATE2_RD_DATA..CREATE2_RD_DATA + rdesc.len()].copy_from_slice(rdesc);
+    Ok(buf)
+}
+
+// struct uhid_input2_req {
+// 	__u16 size;
+// 	__u8 data[UHID_DATA_MAX];
+// } __attribute__((__packed__));
+fn input2_event(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() > MAX_DATA_SIZE {
+        bail!("Report too long: {} bytes", data.len());
+    }
+    let mut buf = vec![0u8; UHID_EVENT_SIZE];
+    buf[0..4].copy_from_slice(&UHID_INPUT2.to_ne_bytes());
+    buf[4..6].copy_from_slice(&(data.len() as u16).to_ne_bytes());
+    buf[6..6 + data.len()].copy_from_slice(data);
+    Ok(buf)
+}
+
+// struct uhid_get_report_reply_req {
+// 	__u32 id;
+// 	__u16 err;
+// 	__u16 size;
+// 	__u8 data[UHID_DATA_MAX];
+// } __attribute__((__packed__));
+fn get_report_reply_event(id: u32, err: u16, data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() > MAX_DATA_SIZE {
+        bail!("Report too long: {} bytes", data.len());
+    }
+    let mut buf = vec![0u8; UHID_EVENT_SIZE];
+    buf[0..4].copy_from_slice(&UHID_GET_REPORT_REPLY.to_ne_bytes());
+    buf[4..8].copy_from_slice(&id.to_ne_bytes());
+    buf[8..10].copy_from_slice(&err.to_ne_bytes());
+    buf[10..12].copy_from_slice(&(data.len() as u16).to_ne_bytes());
+    buf[12..12 + data.len()].copy_from_slice(data);
+    Ok(buf)
+}
+
+fn parse_event(buf: &[u8]) -> Result<UhidEvent> {
+    if buf.len() < 4 {
+        bail!("Short uhid event of {} bytes", buf.len());
+    }
+    let etype = u32::from_ne_bytes(buf[0..4].try_into().unwrap());
+    let event = match etype {
+        UHID_START => UhidEvent::Start,
+        UHID_STOP => UhidEvent::Stop,
+        UHID_OPEN => UhidEvent::Open,
+        UHID_CLOSE => UhidEvent::Close,
+        // struct uhid_output_req {
+        // 	__u8 data[UHID_DATA_MAX];
+        // 	__u16 size;
+        // 	__u8 rtype;
+        // } __attribute__((__packed__));
+        UHID_OUTPUT => {
+            let payload = buf
+                .get(4..4 + MAX_DATA_SIZE + 3)
+                .context("Short uhid output event")?;
+            let size = u16::from_ne_bytes([payload[MAX_DATA_SIZE], payload[MAX_DATA_SIZE + 1]]);
+            let size = (size as usize).min(MAX_DATA_SIZE);
+            UhidEvent::Output {
+                rtype: UhidReportType::try_from(payload[MAX_DATA_SIZE + 2])?,
+                data: payload[..size].to_vec(),
+            }
+        }
+        // struct uhid_get_report_req {
+        // 	__u32 id;
+        // 	__u8 rnum;
+        // 	__u8 rtype;
+        // } __attribute__((__packed__));
+        UHID_GET_REPORT => {
+            let payload = buf.get(4..10).context("Short uhid get report event")?;
+            UhidEvent::GetReport {
+                id: u32::from_ne_bytes(payload[0..4].try_into().unwrap()),
+                rnum: payload[4],
+                rtype: UhidReportType::try_from(payload[5])?,
+            }
+        }
+        // struct uhid_set_report_req {
+        // 	__u32 id;
+        // 	__u8 rnum;
+        // 	__u8 rtype;
+        // 	__u16 size;
+        // 	__u8 data[UHID_DATA_MAX];
+        // } __attribute__((__packed__));
+        UHID_SET_REPORT => {
+            let payload = buf
+                .get(4..12 + MAX_DATA_SIZE)
+                .context("Short uhid set report event")?;
+            let size = u16::from_ne_bytes([payload[6], payload[7]]);
+            let size = (size as usize).min(MAX_DATA_SIZE);
+            UhidEvent::SetReport {
+                id: u32::from_ne_bytes(payload[0..4].try_into().unwrap()),
+                rnum: payload[4],
+                rtype: UhidReportType::try_from(payload[5])?,
+                data: payload[8..8 + size].to_vec(),
+            }
+        }
+        other => UhidEvent::Other(other),
+    };
+    Ok(event)
+}
+
+/// A virtual HID device created through /dev/uhid. The device
+/// is destroyed when this struct is dropped.
+pub struct UhidDevice {
+    file: File,
+}
+
+impl UhidDevice {
+    /// Create a new uhid device with the given name, ids and report descriptor.
+    pub fn create(name: &str, bustype: u16, vid: u32, pid: u32, rdesc: &[u8]) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_CLOEXEC)
+            .open("/dev/uhid")
+            .context("Failed to open /dev/uhid")?;
+        file.write_all(&create2_event(name, bustype, vid, pid, rdesc)?)
+            .context("Failed to create uhid device")?;
+        Ok(UhidDevice { file })
+    }
+
+    /// Inject an input report into the kernel
+    pub fn input(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(&input2_event(data)?)?;
+        Ok(())
+    }
+
+    /// Answer a [UhidEvent::GetReport] with the report data or with
+    /// an errno, in which case the data is ignored
+    pub fn get_report_reply(&mut self, id: u32, err: u16, data: &[u8]) -> Result<()> {
+        self.file
+            .write_all(&get_report_reply_event(id, err, data)?)?;
+        Ok(())
+    }
+
+    /// Answer a [UhidEvent::SetReport] with success or an errno
+    pub fn set_report_reply(&mut self, id: u32, err: u16) -> Result<()> {
+        let mut buf = vec![0u8; UHID_EVENT_SIZE];
+        buf[0..4].copy_from_slice(&UHID_SET_REPORT_REPLY.to_ne_bytes());
+        buf[4..8].copy_from_slice(&id.to_ne_bytes());
+        buf[8..10].copy_from_slice(&err.to_ne_bytes());
+        self.file.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Read the next event, this blocks until one is available
+    pub fn read_event(&mut self) -> Result<UhidEvent> {
+        let mut buf = [0u8; UHID_EVENT_SIZE];
+        let nbytes = self.file.read(&mut buf)?;
+        parse_event(&buf[..nbytes])
+    }
+
+    pub fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl Drop for UhidDevice {
+    fn drop(&mut self) {
+        let mut buf = vec![0u8; UHID_EVENT_SIZE];
+        buf[0..4].copy_from_slice(&UHID_DESTROY.to_ne_bytes());
+        let _ = self.file.write_all(&buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create2_event() {
+        let rdesc = [0x05, 0x01, 0x09, 0x02];
+        let buf = create2_event("Test Mouse", 0x3, 0x1234, 0x5678, &rdesc).unwrap();
+        assert_eq!(buf.len(), UHID_EVENT_SIZE);
+        assert_eq!(u32::from_ne_bytes(buf[0..4].try_into().unwrap()), 11);
+        assert_eq!(&buf[4..14], b"Test Mouse");
+        assert_eq!(buf[14], 0);
+        assert_eq!(u16::from_ne_bytes([buf[260], buf[261]]), 4);
+        assert_eq!(u16::from_ne_bytes([buf[262], buf[263]]), 0x3);
+        assert_eq!(
+            u32::from_ne_bytes(buf[264..268].try_into().unwrap()),
+            0x1234
+        );
+        assert_eq!(
+            u32::from_ne_bytes(buf[268..272].try_into().unwrap()),
+            0x5678
+        );
+        assert_eq!(&buf[280..284], &rdesc);
+
+        assert!(create2_event("Test", 0x3, 0x1, 0x1, &[0; 4097]).is_err());
+    }
+
+    #[test]
+    fn test_parse_event() {
+        let mut buf = vec![0u8; UHID_EVENT_SIZE];
+        buf[0..4].copy_from_slice(&UHID_OUTPUT.to_ne_bytes());
+        buf[4..6].copy_from_slice(&[0x01, 0x02]);
+        buf[4 + 4096..4 + 4098].copy_from_slice(&2u16.to_ne_bytes());
+        buf[4 + 4098] = 1;
+        assert_eq!(
+            parse_event(&buf).unwrap(),
+            UhidEvent::Output {
+                rtype: UhidReportType::Output,
+                data: vec![0x01, 0x02],
+            }
+        );
+
+        buf[0..4].copy_from_slice(&UHID_OPEN.to_ne_bytes());
+        assert_eq!(parse_event(&buf).unwrap(), UhidEvent::Open);
+
+        let mut buf = vec![0u8; UHID_EVENT_SIZE];
+        buf[0..4].copy_from_slice(&UHID_GET_REPORT.to_ne_bytes());
+        buf[4..8].copy_from_slice(&42u32.to_ne_bytes());
+        buf[8] = 3;
+        buf[9] = 0;
+        assert_eq!(
+            parse_event(&buf).unwrap(),
+            UhidEvent::GetReport {
+                id: 42,
+                rnum: 3,
+                rtype: UhidReportType::Feature,
+            }
+        );
+
+        buf[0..4].copy_from_slice(&UHID_SET_REPORT.to_ne_bytes());
+        buf[10..12].copy_from_slice(&2u16.to_ne_bytes());
+        buf[12..14].copy_from_slice(&[0x03, 0x02]);
+        assert_eq!(
+            parse_event(&buf).unwrap(),
+            UhidEvent::SetReport {
+                id: 42,
+                rnum: 3,
+                rtype: UhidReportType::Feature,
+                data: vec![0x03, 0x02],
+            }
+        );
+        assert!(parse_event(&buf[..2]).is_err());
+    }
+}
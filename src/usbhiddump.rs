@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: MIT
+//
+// Reads the output of usbhid-dump, e.g.
+//
+// 001:004:000:DESCRIPTOR         1373549370.364457
+//  05 01 09 02 A1 01 09 01 A1 00 05 09 19 01 29 03
+//  ...
+//
+// 001:004:000:STREAM             1373549376.582830
+//  00 01 00 00
+
+use anyhow::{bail, Context, Result};
+use std::io::BufRead;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{
+    Backend, BpfOption, Event, EventNode, Outfile, ReportDescriptor, ReportDirection, ReportEvent,
+    Styles,
+};
+
+struct UsbhidDumpEvent {
+    usecs: u64,
+    bytes: Vec<u8>,
+}
+
+/// The descriptor and events of one interface
+struct Interface {
+    bus: u16,
+    device: u16,
+    interface: u8,
+    rdesc: Vec<u8>,
+    events: Vec<UsbhidDumpEvent>,
+}
+
+pub struct UsbhidDumpBackend {
+    name: String,
+    rdesc: Vec<u8>,
+    events: Vec<UsbhidDumpEvent>,
+}
+
+enum Block {
+    Descriptor,
+    Stream,
+}
+
+/// Parse a block header, e.g. `001:004:000:STREAM   1373549376.582830`
+fn parse_header(line: &str) -> Option<(u16, u16, u8, Block, u64)> {
+    let (address, timestamp) = line.split_once(char::is_whitespace)?;
+    let mut parts = address.split(':');
+    let bus = parts.next()?.parse::<u16>().ok()?;
+    let device = parts.next()?.parse::<u16>().ok()?;
+    let interface = parts.next()?.parse::<u8>().ok()?;
+    let block = match parts.next()? {
+        "DESCRIPTOR" => Block::Descriptor,
+        "STREAM" => Block::Stream,
+        _ => return None,
+    };
+    let (secs, usecs) = timestamp.trim().split_once('.')?;
+    let usecs = secs.parse::<u64>().ok()? * 1_000_000 + usecs.parse::<u64>().ok()?;
+    Some((bus, device, interface, block, usecs))
+}
+
+/// Whether `data` looks like usbhid-dump output, i.e. starts with a
+/// block header
+pub fn is_usbhid_dump(data: &[u8]) -> bool {
+    String::from_utf8_lossy(data)
+        .lines()
+        .find(|l| !l.trim().is_empty())
+        .and_then(parse_header)
+        .is_some()
+}
+
+impl UsbhidDumpBackend {
+    /// Read the usbhid-dump output in `path`. If it contains several
+    /// interfaces, use the one with this interface number or the first
+    /// one if `interface` is `None`. The event timestamps are relative to
+    /// the first event of that interface.
+    pub fn from_path_and_interface(path: &Path, interface: Option<u8>) -> Result<Self> {
+        let f = std::fs::File::open(path)?;
+        let lines = std::io::BufReader::new(f).lines().map_while(Result::ok);
+
+        let mut interfaces: Vec<Interface> = Vec::new();
+        // The interface index and block type of the current block
+        let mut current: Option<(usize, Block)> = None;
+
+        for line in lines {
+            if line.trim().is_empty() {
+                current = None;
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace) {
+                let Some((bus, device, iface, block, usecs)) = parse_header(&line) else {
+                    bail!("Invalid usbhid-dump header line: {line}");
+                };
+                let idx = match interfaces
+                    .iter()
+                    .position(|i| (i.bus, i.device, i.interface) == (bus, device, iface))
+                {
+                    Some(idx) => idx,
+                    None => {
+                        interfaces.push(Interface {
+                            bus,
+                            device,
+                            interface: iface,
+                            rdesc: Vec::new(),
+                            events: Vec::new(),
+                        });
+                        interfaces.len() - 1
+                    }
+                };
+                if let Block::Stream = block {
+                    interfaces[idx].events.push(UsbhidDumpEvent {
+                        usecs,
+                        bytes: Vec::new(),
+                    });
+                }
+                current = Some((idx, block));
+                continue;
+            }
+
+            let Some((idx, ref block)) = current else {
+                bail!("Data outside of a descriptor or stream block: {line}");
+            };
+            let bytes = hex::decode(line.replace(char::is_whitespace, ""))
+                .context(format!("Invalid data line: {line}"))?;
+            let iface = &mut interfaces[idx];
+            match block {
+                Block::Descriptor => iface.rdesc.extend(bytes),
+                Block::Stream => iface.events.last_mut().unwrap().bytes.extend(bytes),
+            }
+        }
+
+        interfaces.retain(|i| !i.rdesc.is_empty());
+        let available = interfaces
+            .iter()
+            .map(|i| format!("{}", i.interface))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let mut iface = match interface {
+            Some(number) => match interfaces.iter().position(|i| i.interface == number) {
+                Some(idx) => interfaces.swap_remove(idx),
+                None => bail!("No interface {number}, available interfaces: {available}"),
+            },
+            None if interfaces.len() > 1 => {
+                Outfile::new().write_comment_styled(
+                    Styles::Note,
+                    &format!(
+                        "Using interface {} of interfaces {available}, select another one with --interface",
+                        interfaces[0].interface
+                    ),
+                );
+                interfaces.swap_remove(0)
+            }
+            None if !interfaces.is_empty() => interfaces.swap_remove(0),
+            None => bail!("No report descriptor found"),
+        };
+
+        let start = iface.events.first().map(|e| e.usecs).unwrap_or(0);
+        for e in iface.events.iter_mut() {
+            e.usecs = e.usecs.saturating_sub(start);
+        }
+
+        Ok(UsbhidDumpBackend {
+            name: format!(
+                "usbhid-dump bus {:03} device {:03} interface {}",
+                iface.bus, iface.device, iface.interface
+            ),
+            rdesc: iface.rdesc,
+            events: iface.events,
+        })
+    }
+}
+
+impl TryFrom<&Path> for UsbhidDumpBackend {
+    type Error = anyhow::Error;
+
+    fn try_from(path: &Path) -> Result<Self> {
+        UsbhidDumpBackend::from_path_and_interface(path, None)
+    }
+}
+
+impl Backend for UsbhidDumpBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bustype(&self) -> u32 {
+        // usbhid-dump only supports USB devices
+        0x03
+    }
+
+    // usbhid-dump does not print vendor and product id
+    fn vid(&self) -> u32 {
+        0x0
+    }
+
+    fn pid(&self) -> u32 {
+        0x0
+    }
+
+    fn rdesc(&self) -> &[u8] {
+        &self.rdesc
+    }
+
+    fn event_nodes(&self) -> &[EventNode] {
+        &[]
+    }
+
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        rdesc: &ReportDescriptor,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
+        for e in self.events.iter() {
+            let elapsed = Duration::from_micros(e.usecs);
+            callback(Event::Report(ReportEvent::new(
+                &e.bytes,
+                ReportDirection::Input,
+                rdesc,
+                elapsed,
+                None,
+            )))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    // A keyboard interface and a mouse interface: 3 buttons, 5 bits
+    // padding, X/Y as signed 8 bit relative values
+    const DUMP: &str = "\
+001:004:000:DESCRIPTOR         1373549370.364457
+ 05 01 09 06 A1 01 05 07 19 E0 29 E7 15 00 25 01
+ 75 01 95 08 81 02 C0
+
+001:004:001:DESCRIPTOR         1373549370.365104
+ 05 01 09 02 A1 01 09 01 A1 00 05 09 19 01 29 03
+ 15 00 25 01 95 03 75 01 81 02 95 01 75 05 81 01
+ 05 01 09 30 09 31 15 81 25 7F 75 08 95 02 81 06
+ C0 C0
+
+001:004:001:STREAM             1373549376.582830
+ 01 FF 02
+
+001:004:000:STREAM             1373549376.600000
+ 02
+
+001:004:001:STREAM             1373549377.583330
+ 00 05 00
+";
+
+    #[test]
+    fn test_usbhid_dump() {
+        assert!(is_usbhid_dump(DUMP.as_bytes()));
+        assert!(!is_usbhid_dump(b" 05 01 09 02"));
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(DUMP.as_bytes()).unwrap();
+        file.flush().unwrap();
+
+        let mut keyboard = None;
+        let output = crate::tests::capture_output(|| {
+            keyboard = Some(UsbhidDumpBackend::try_from(file.path()).unwrap());
+        });
+        assert!(output.contains("Using interface 0 of interfaces 0, 1"));
+        let keyboard = keyboard.unwrap();
+        assert_eq!(keyboard.rdesc().len(), 23);
+        assert_eq!(keyboard.events.len(), 1);
+        assert_eq!(keyboard.events[0].usecs, 0);
+
+        let mouse = UsbhidDumpBackend::from_path_and_interface(file.path(), Some(1)).unwrap();
+        assert_eq!(mouse.name(), "usbhid-dump bus 001 device 004 interface 1");
+        assert_eq!(mouse.rdesc().len(), 50);
+
+        let rdesc = ReportDescriptor::try_from(mouse.rdesc()).unwrap();
+        let mut events = Vec::new();
+        mouse
+            .read_events(BpfOption::Never, &rdesc, &mut |e| {
+                events.push(e);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        let Event::Report(ref e) = events[1] else {
+            panic!("Expected a report event");
+        };
+        assert_eq!(e.elapsed, Duration::from_micros(1_000_500));
+        assert_eq!(e.bytes, vec![0x00, 0x05, 0x00]);
+
+        let missing = UsbhidDumpBackend::from_path_and_interface(file.path(), Some(2));
+        assert!(missing
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("available interfaces: 0, 1"));
+    }
+}
@@ -0,0 +1,793 @@
+// SPDX-License-Identifier: MIT
+//
+// Reads USB HID traffic captured with the kernel's usbmon, either in the
+// text format of /sys/kernel/debug/usb/usbmon/<bus>u:
+//
+// d5ea89a0 3575914555 S Ci:1:004:0 s 81 06 2200 0000 001d 29 <
+// d5ea89a0 3575914560 C Ci:1:004:0 0 29 = 05010902 a1010509 ...
+//
+// or as pcap/pcapng file (e.g. from Wireshark) with the link types
+// LINKTYPE_USB_LINUX or LINKTYPE_USB_LINUX_MMAPPED.
+//
+// The report descriptor comes from the GET_DESCRIPTOR(Report) control
+// transfer during enumeration, the events from the interrupt IN
+// transfers on the interface's endpoint.
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{Backend, BpfOption, Event, EventNode, ReportDescriptor, ReportDirection, ReportEvent};
+
+const LINKTYPE_USB_LINUX: u32 = 189;
+const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+const USB_REQ_GET_DESCRIPTOR: u8 = 0x06;
+const USB_DT_DEVICE: u8 = 0x01;
+const USB_DT_CONFIG: u8 = 0x02;
+const USB_DT_STRING: u8 = 0x03;
+const USB_DT_INTERFACE: u8 = 0x04;
+const USB_DT_ENDPOINT: u8 = 0x05;
+const HID_DT_REPORT: u8 = 0x22;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TransferType {
+    Isochronous,
+    Interrupt,
+    Control,
+    Bulk,
+}
+
+/// One usbmon event, the submission or the completion of an URB
+#[derive(Debug)]
+struct UrbPacket {
+    id: u64,
+    /// `S` for submission, `C` for completion, `E` for errors
+    event: u8,
+    transfer_type: TransferType,
+    /// The endpoint number, with 0x80 set for IN endpoints
+    endpoint: u8,
+    bus: u16,
+    device: u8,
+    usecs: u64,
+    status: i32,
+    setup: Option<[u8; 8]>,
+    data: Vec<u8>,
+    /// The length of the transferred data, this is larger than `data`
+    /// where the capture truncated the data.
+    length: usize,
+}
+
+/// Parse a line of the usbmon text format, see the kernel's
+/// Documentation/usb/usbmon.rst
+fn parse_text_line(line: &str) -> Result<UrbPacket> {
+    let mut tokens = line.split_whitespace();
+    let mut next = || tokens.next().context("Incomplete usbmon line");
+
+    let id = u64::from_str_radix(next()?, 16)?;
+    let usecs = next()?.parse::<u64>()?;
+    let event = match next()? {
+        "S" => b'S',
+        "C" => b'C',
+        "E" => b'E',
+        e => bail!("Invalid usbmon event type {e}"),
+    };
+    // e.g. Ii:1:004:1
+    let address = next()?;
+    let mut parts = address.split(':');
+    let (urb_type, bus, device, endpoint) = (
+        parts.next().context("Invalid address")?,
+        parts.next().context("Invalid address")?.parse::<u16>()?,
+        parts.next().context("Invalid address")?.parse::<u8>()?,
+        parts.next().context("Invalid address")?.parse::<u8>()?,
+    );
+    let transfer_type = match urb_type.as_bytes().first() {
+        Some(b'Z') => TransferType::Isochronous,
+        Some(b'I') => TransferType::Interrupt,
+        Some(b'C') => TransferType::Control,
+        Some(b'B') => TransferType::Bulk,
+        _ => bail!("Invalid URB type {urb_type}"),
+    };
+    let endpoint = match urb_type.as_bytes().get(1) {
+        Some(b'i') => endpoint | 0x80,
+        Some(b'o') => endpoint,
+        _ => bail!("Invalid URB direction {urb_type}"),
+    };
+
+    let (setup, status) = match next()? {
+        "s" => {
+            let mut setup = [0u8; 8];
+            setup[0] = u8::from_str_radix(next()?, 16)?;
+            setup[1] = u8::from_str_radix(next()?, 16)?;
+            for i in 0..3 {
+                let word = u16::from_str_radix(next()?, 16)?;
+                setup[2 + i * 2..4 + i * 2].copy_from_slice(&word.to_le_bytes());
+            }
+            (Some(setup), 0)
+        }
+        // Interrupt and isochronous transfers have more fields after the
+        // status, e.g. 0:8
+        status => (None, status.split(':').next().unwrap_or("").parse::<i32>()?),
+    };
+    let length = next()?.parse::<usize>()?;
+    let data = match next() {
+        Ok("=") => hex::decode(tokens.collect::<String>())?,
+        _ => Vec::new(),
+    };
+
+    Ok(UrbPacket {
+        id,
+        event,
+        transfer_type,
+        endpoint,
+        bus,
+        device,
+        usecs,
+        status,
+        setup,
+        data,
+        length,
+    })
+}
+
+/// Reads integers in the byte order of the capture file
+#[derive(Clone, Copy)]
+struct Reader {
+    big_endian: bool,
+}
+
+impl Reader {
+    fn u16(&self, bytes: &[u8], offset: usize) -> Option<u16> {
+        let b: [u8; 2] = bytes.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, bytes: &[u8], offset: usize) -> Option<u32> {
+        let b: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn u64(&self, bytes: &[u8], offset: usize) -> Option<u64> {
+        let b: [u8; 8] = bytes.get(offset..offset + 8)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        })
+    }
+}
+
+/// Parse a packet with the usbmon binary header (struct usbmon_packet),
+/// the header is 48 bytes for LINKTYPE_USB_LINUX and 64 bytes for
+/// LINKTYPE_USB_LINUX_MMAPPED.
+fn parse_usbmon_packet(bytes: &[u8], linktype: u32, reader: Reader) -> Option<UrbPacket> {
+    let header_len = match linktype {
+        LINKTYPE_USB_LINUX => 48,
+        LINKTYPE_USB_LINUX_MMAPPED => 64,
+        _ => return None,
+    };
+    let transfer_type = match bytes.get(9)? {
+        0 => TransferType::Isochronous,
+        1 => TransferType::Interrupt,
+        2 => TransferType::Control,
+        _ => TransferType::Bulk,
+    };
+    let setup = match bytes.get(14)? {
+        0 => Some(bytes.get(40..48)?.try_into().ok()?),
+        _ => None,
+    };
+    let data_len = reader.u32(bytes, 36)? as usize;
+    // Isochronous descriptors sit between header and data, we don't
+    // handle those
+    let data = match bytes.get(15)? {
+        0 if transfer_type != TransferType::Isochronous => bytes
+            .get(header_len..(header_len + data_len).min(bytes.len()))?
+            .to_vec(),
+        _ => Vec::new(),
+    };
+    let secs = reader.u64(bytes, 16)?;
+    let usecs = reader.u32(bytes, 24)?;
+
+    Some(UrbPacket {
+        id: reader.u64(bytes, 0)?,
+        event: *bytes.get(8)?,
+        transfer_type,
+        endpoint: *bytes.get(10)?,
+        device: *bytes.get(11)?,
+        bus: reader.u16(bytes, 12)?,
+        usecs: secs * 1_000_000 + u64::from(usecs),
+        status: reader.u32(bytes, 28)? as i32,
+        setup,
+        data,
+        length: reader.u32(bytes, 32)? as usize,
+    })
+}
+
+/// The usbmon packets of a pcap file
+fn parse_pcap(bytes: &[u8]) -> Result<Vec<UrbPacket>> {
+    let big_endian = match bytes.get(0..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1]) | Some([0x4d, 0x3c, 0xb2, 0xa1]) => false,
+        Some([0xa1, 0xb2, 0xc3, 0xd4]) | Some([0xa1, 0xb2, 0x3c, 0x4d]) => true,
+        _ => bail!("Not a pcap file"),
+    };
+    let reader = Reader { big_endian };
+    let linktype = reader.u32(bytes, 20).context("Truncated pcap header")?;
+    if linktype != LINKTYPE_USB_LINUX && linktype != LINKTYPE_USB_LINUX_MMAPPED {
+        bail!("Unsupported pcap link type {linktype}, expected a usbmon capture");
+    }
+
+    let mut packets = Vec::new();
+    let mut offset = 24;
+    while offset < bytes.len() {
+        let caplen = reader
+            .u32(bytes, offset + 8)
+            .context("Truncated pcap record")? as usize;
+        let record = bytes
+            .get(offset + 16..offset + 16 + caplen)
+            .context("Truncated pcap record")?;
+        if let Some(packet) = parse_usbmon_packet(record, linktype, reader) {
+            packets.push(packet);
+        }
+        offset += 16 + caplen;
+    }
+    Ok(packets)
+}
+
+/// The usbmon packets of a pcapng file
+fn parse_pcapng(bytes: &[u8]) -> Result<Vec<UrbPacket>> {
+    const SHB: u32 = 0x0a0d0d0a;
+    const IDB: u32 = 0x00000001;
+    const SPB: u32 = 0x00000003;
+    const EPB: u32 = 0x00000006;
+
+    let mut packets = Vec::new();
+    let mut reader = Reader { big_endian: false };
+    let mut linktypes: Vec<u32> = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        if reader.u32(bytes, offset) == Some(SHB) {
+            // The byte order magic decides the byte order of this section
+            reader.big_endian = match bytes.get(offset + 8..offset + 12) {
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => false,
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => true,
+                _ => bail!("Invalid pcapng byte order magic"),
+            };
+            linktypes.clear();
+        }
+        let block_type = reader
+            .u32(bytes, offset)
+            .context("Truncated pcapng block")?;
+        let block_len = reader
+            .u32(bytes, offset + 4)
+            .context("Truncated pcapng block")? as usize;
+        if block_len < 12 {
+            bail!("Invalid pcapng block length {block_len}");
+        }
+        let block = bytes
+            .get(offset..offset + block_len)
+            .context("Truncated pcapng block")?;
+        match block_type {
+            IDB => linktypes.push(u32::from(reader.u16(block, 8).context("Invalid IDB")?)),
+            EPB => {
+                let interface = reader.u32(block, 8).context("Invalid EPB")? as usize;
+                let caplen = reader.u32(block, 20).context("Invalid EPB")? as usize;
+                let data = block.get(28..28 + caplen).context("Invalid EPB")?;
+                if let Some(linktype) = linktypes.get(interface) {
+                    packets.extend(parse_usbmon_packet(data, *linktype, reader));
+                }
+            }
+            SPB => {
+                let len = reader.u32(block, 8).context("Invalid SPB")? as usize;
+                let data = block
+                    .get(12..(12 + len).min(block_len - 4))
+                    .context("Invalid SPB")?;
+                if let Some(linktype) = linktypes.first() {
+                    packets.extend(parse_usbmon_packet(data, *linktype, reader));
+                }
+            }
+            _ => {}
+        }
+        offset += block_len;
+    }
+    if !linktypes
+        .iter()
+        .any(|l| *l == LINKTYPE_USB_LINUX || *l == LINKTYPE_USB_LINUX_MMAPPED)
+    {
+        bail!("No usbmon interface in this pcapng file");
+    }
+    Ok(packets)
+}
+
+/// What we learned about a USB device from its enumeration
+#[derive(Default)]
+struct UsbDevice {
+    vid: u16,
+    pid: u16,
+    manufacturer: u8,
+    product: u8,
+    strings: HashMap<u8, String>,
+    /// The interrupt IN endpoints of each interface
+    endpoints: HashMap<u8, Vec<u8>>,
+    /// The report descriptor of each interface and whether the capture
+    /// truncated it
+    rdescs: Vec<(u8, Vec<u8>, bool)>,
+}
+
+impl UsbDevice {
+    fn parse_descriptor(&mut self, setup: &[u8; 8], data: &[u8], truncated: bool) {
+        let (index, dtype) = (setup[2], setup[3]);
+        let interface = setup[4];
+        match dtype {
+            USB_DT_DEVICE if data.len() >= 16 => {
+                self.vid = u16::from_le_bytes([data[8], data[9]]);
+                self.pid = u16::from_le_bytes([data[10], data[11]]);
+                self.manufacturer = data[14];
+                self.product = data[15];
+            }
+            USB_DT_CONFIG => {
+                let mut current_interface = None;
+                let mut offset = 0;
+                while let Some(&[len, dtype, ..]) = data.get(offset..offset + 4) {
+                    let len = usize::from(len);
+                    if len < 2 || offset + len > data.len() {
+                        break;
+                    }
+                    let desc = &data[offset..offset + len];
+                    match dtype {
+                        USB_DT_INTERFACE => current_interface = Some(desc[2]),
+                        USB_DT_ENDPOINT
+                            if len >= 4 && desc[2] & 0x80 != 0 && desc[3] & 0x3 == 3 =>
+                        {
+                            if let Some(interface) = current_interface {
+                                let endpoints = self.endpoints.entry(interface).or_default();
+                                if !endpoints.contains(&desc[2]) {
+                                    endpoints.push(desc[2]);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    offset += len;
+                }
+            }
+            USB_DT_STRING if index != 0 && data.len() >= 2 => {
+                let len = usize::from(data[0]).min(data.len());
+                let utf16: Vec<u16> = data[2..len]
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                self.strings.insert(index, String::from_utf16_lossy(&utf16));
+            }
+            HID_DT_REPORT if !self.rdescs.iter().any(|(i, _, _)| *i == interface) => {
+                self.rdescs.push((interface, data.to_vec(), truncated));
+            }
+            _ => {}
+        }
+    }
+
+    fn name(&self) -> Option<String> {
+        let manufacturer = self.strings.get(&self.manufacturer);
+        let product = self.strings.get(&self.product);
+        match (manufacturer, product) {
+            (Some(manufacturer), Some(product)) => Some(format!("{manufacturer} {product}")),
+            (None, Some(product)) => Some(product.clone()),
+            _ => None,
+        }
+    }
+}
+
+struct UsbmonEvent {
+    usecs: u64,
+    bytes: Vec<u8>,
+}
+
+pub struct UsbmonBackend {
+    name: String,
+    vid: u32,
+    pid: u32,
+    rdesc: Vec<u8>,
+    events: Vec<UsbmonEvent>,
+}
+
+/// Whether `data` looks like a usbmon capture, i.e. is a pcap or pcapng
+/// file or starts with a line in the usbmon text format
+pub fn is_usbmon_capture(data: &[u8]) -> bool {
+    match data.get(0..4) {
+        Some([0x0a, 0x0d, 0x0d, 0x0a])
+        | Some([0xd4, 0xc3, 0xb2, 0xa1])
+        | Some([0x4d, 0x3c, 0xb2, 0xa1])
+        | Some([0xa1, 0xb2, 0xc3, 0xd4])
+        | Some([0xa1, 0xb2, 0x3c, 0x4d]) => true,
+        _ => String::from_utf8_lossy(data)
+            .lines()
+            .find(|l| !l.trim().is_empty())
+            .is_some_and(|l| parse_text_line(l).is_ok()),
+    }
+}
+
+impl UsbmonBackend {
+    /// Read the usbmon capture in `path`. If it contains several report
+    /// descriptors, use the one of the interface with this number or the
+    /// first one if `interface` is `None`.
+    pub fn from_path_and_interface(path: &Path, interface: Option<u8>) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let packets = match bytes.get(0..4) {
+            Some([0x0a, 0x0d, 0x0d, 0x0a]) => parse_pcapng(&bytes)?,
+            Some([0xd4, 0xc3, 0xb2, 0xa1])
+            | Some([0x4d, 0x3c, 0xb2, 0xa1])
+            | Some([0xa1, 0xb2, 0xc3, 0xd4])
+            | Some([0xa1, 0xb2, 0x3c, 0x4d]) => parse_pcap(&bytes)?,
+            _ => String::from_utf8(bytes)?
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(parse_text_line)
+                .collect::<Result<Vec<UrbPacket>>>()?,
+        };
+        UsbmonBackend::from_packets(&packets, interface)
+    }
+
+    fn from_packets(packets: &[UrbPacket], interface: Option<u8>) -> Result<Self> {
+        // Devices in the order we first see them
+        let mut devices: Vec<((u16, u8), UsbDevice)> = Vec::new();
+        // The setup packets of the control transfers without completion
+        let mut pending: HashMap<(u16, u8, u64), [u8; 8]> = HashMap::new();
+
+        for p in packets
+            .iter()
+            .filter(|p| p.transfer_type == TransferType::Control)
+        {
+            let key = (p.bus, p.device, p.id);
+            match (p.event, p.setup) {
+                (b'S', Some(setup)) => {
+                    pending.insert(key, setup);
+                }
+                (b'C', _) => {
+                    let Some(setup) = pending.remove(&key) else {
+                        continue;
+                    };
+                    // Standard GET_DESCRIPTOR or HID class GET_DESCRIPTOR
+                    // on the interface
+                    if setup[1] != USB_REQ_GET_DESCRIPTOR || setup[0] & 0x80 == 0 || p.status != 0 {
+                        continue;
+                    }
+                    let idx = match devices
+                        .iter()
+                        .position(|(addr, _)| *addr == (p.bus, p.device))
+                    {
+                        Some(idx) => idx,
+                        None => {
+                            devices.push(((p.bus, p.device), UsbDevice::default()));
+                            devices.len() - 1
+                        }
+                    };
+                    let truncated = p.data.len() < p.length;
+                    devices[idx].1.parse_descriptor(&setup, &p.data, truncated);
+                }
+                _ => {}
+            }
+        }
+
+        let mut rdescs = devices
+            .iter()
+            .flat_map(|(addr, dev)| dev.rdescs.iter().map(move |r| (addr, dev, r)));
+        let (&(bus, devnum), device, (iface, rdesc, truncated)) = match interface {
+            Some(number) => rdescs
+                .find(|(_, _, (i, _, _))| *i == number)
+                .context(format!("No report descriptor for interface {number}"))?,
+            None => rdescs.next().context(
+                "No GET_DESCRIPTOR(Report) request found, capture the device's enumeration",
+            )?,
+        };
+        if *truncated {
+            bail!("The report descriptor is truncated, the usbmon text format only has the first 32 bytes of each transfer. Use a pcap capture instead");
+        }
+
+        let endpoints: Vec<u8> = match device.endpoints.get(iface) {
+            Some(endpoints) => endpoints.clone(),
+            // Without the configuration descriptor we can only guess the
+            // endpoint for devices with a single HID interface
+            None if device.rdescs.len() == 1 => packets
+                .iter()
+                .filter(|p| {
+                    (p.bus, p.device) == (bus, devnum)
+                        && p.transfer_type == TransferType::Interrupt
+                        && p.endpoint & 0x80 != 0
+                })
+                .map(|p| p.endpoint)
+                .collect(),
+            None => {
+                bail!("No configuration descriptor found to find the endpoint of interface {iface}")
+            }
+        };
+
+        let events: Vec<&UrbPacket> = packets
+            .iter()
+            .filter(|p| {
+                p.event == b'C'
+                    && p.status == 0
+                    && !p.data.is_empty()
+                    && p.transfer_type == TransferType::Interrupt
+                    && (p.bus, p.device) == (bus, devnum)
+                    && endpoints.contains(&p.endpoint)
+            })
+            .collect();
+        let start = events.first().map_or(0, |e| e.usecs);
+
+        Ok(UsbmonBackend {
+            name: device.name().unwrap_or(format!(
+                "usbmon bus {bus} device {devnum} interface {iface}"
+            )),
+            vid: u32::from(device.vid),
+            pid: u32::from(device.pid),
+            rdesc: rdesc.clone(),
+            events: events
+                .iter()
+                .map(|e| UsbmonEvent {
+                    usecs: e.usecs.saturating_sub(start),
+                    bytes: e.data.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl TryFrom<&Path> for UsbmonBackend {
+    type Error = anyhow::Error;
+
+    fn try_from(path: &Path) -> Result<Self> {
+        UsbmonBackend::from_path_and_interface(path, None)
+    }
+}
+
+impl Backend for UsbmonBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn bustype(&self) -> u32 {
+        // BUS_USB
+        0x03
+    }
+
+    fn vid(&self) -> u32 {
+        self.vid
+    }
+
+    fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    fn rdesc(&self) -> &[u8] {
+        &self.rdesc
+    }
+
+    fn event_nodes(&self) -> &[EventNode] {
+        &[]
+    }
+
+    fn read_events(
+        &self,
+        _use_bpf: BpfOption,
+        rdesc: &ReportDescriptor,
+        callback: &mut dyn FnMut(Event) -> Result<()>,
+    ) -> Result<()> {
+        for e in self.events.iter() {
+            let elapsed = Duration::from_micros(e.usecs);
+            callback(Event::Report(ReportEvent::new(
+                &e.bytes,
+                ReportDirection::Input,
+                rdesc,
+                elapsed,
+                None,
+            )))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The reply to the report descriptor request in usbmon_packets()
+    const MOUSE: [u8; 50] = [
+        0x05, 0x01, 0x09, 0x02, 0xa1, 0x01, 0x09, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29,
+        0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05,
+        0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7f, 0x75, 0x08, 0x95,
+        0x02, 0x81, 0x06, 0xc0, 0xc0,
+    ];
+
+    // 3 buttons and 5 bits padding only, short enough for the text format
+    const TEXT: &str = "\
+d5ea8900 3575914000 S Ci:1:004:0 s 80 06 0100 0000 0012 18 <
+d5ea8900 3575914010 C Ci:1:004:0 0 18 = 12010002 00000008 6d0477c0 00010102 0001
+d5ea89a0 3575914555 S Ci:1:004:0 s 81 06 2200 0000 001d 29 <
+d5ea89a0 3575914560 C Ci:1:004:0 0 29 = 05010902 a1010509 19012903 15002501 95037501 81029501 75058101 c0
+d5ea8a00 3576000000 S Ii:1:004:1 -115:8 1 <
+d5ea8a00 3576000000 C Ii:1:004:1 0:8 1 = 01
+d5ea8a00 3576000000 S Ii:1:004:1 -115:8 1 <
+d5ea8a00 3576008000 C Ii:1:004:1 0:8 1 = 00
+";
+
+    /// A usbmon packet with the LINKTYPE_USB_LINUX_MMAPPED header
+    fn usbmon_packet(
+        event: u8,
+        transfer_type: u8,
+        endpoint: u8,
+        usecs: u64,
+        setup: Option<[u8; 8]>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut p = vec![0u8; 64];
+        p[0..8].copy_from_slice(&(usecs / 1000).to_le_bytes()); // id
+        p[8] = event;
+        p[9] = transfer_type;
+        p[10] = endpoint;
+        p[11] = 4; // device
+        p[12..14].copy_from_slice(&1u16.to_le_bytes()); // bus
+        p[14] = if setup.is_some() { 0 } else { b'-' };
+        p[15] = if data.is_empty() { b'<' } else { 0 };
+        p[16..24].copy_from_slice(&(usecs / 1_000_000).to_le_bytes());
+        p[24..28].copy_from_slice(&((usecs % 1_000_000) as u32).to_le_bytes());
+        p[32..36].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        p[36..40].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        if let Some(setup) = setup {
+            p[40..48].copy_from_slice(&setup);
+        }
+        p.extend(data);
+        p
+    }
+
+    fn usbmon_packets() -> Vec<Vec<u8>> {
+        let mut config = vec![9, 2, 34, 0, 1, 1, 0, 0xa0, 50];
+        config.extend([9, 4, 0, 0, 1, 3, 1, 2, 0]); // interface 0
+        config.extend([9, 0x21, 0x11, 0x01, 0, 1, 0x22, 50, 0]); // HID
+        config.extend([7, 5, 0x81, 3, 4, 0, 10]); // endpoint 0x81
+        let device = [
+            18, 1, 0, 2, 0, 0, 0, 8, 0x6d, 0x04, 0x77, 0xc0, 0, 1, 0, 2, 0, 1,
+        ];
+        let product = [8, 3, b'M', 0, b'o', 0, b'u', 0];
+        vec![
+            usbmon_packet(b'S', 2, 0x80, 500, Some([0x80, 6, 0, 1, 0, 0, 18, 0]), &[]),
+            usbmon_packet(b'C', 2, 0x80, 500, None, &device),
+            usbmon_packet(
+                b'S',
+                2,
+                0x80,
+                1_000,
+                Some([0x80, 6, 0, 2, 0, 0, 34, 0]),
+                &[],
+            ),
+            usbmon_packet(b'C', 2, 0x80, 1_000, None, &config),
+            usbmon_packet(
+                b'S',
+                2,
+                0x80,
+                2_000,
+                Some([0x80, 6, 2, 3, 9, 4, 255, 0]),
+                &[],
+            ),
+            usbmon_packet(b'C', 2, 0x80, 2_000, None, &product),
+            usbmon_packet(
+                b'S',
+                2,
+                0x80,
+                3_000,
+                Some([0x81, 6, 0, 0x22, 0, 0, 50, 0]),
+                &[],
+            ),
+            usbmon_packet(b'C', 2, 0x80, 3_000, None, &MOUSE),
+            // An interrupt IN transfer on another endpoint
+            usbmon_packet(b'C', 1, 0x82, 1_004_000, None, &[0x02, 0x00]),
+            usbmon_packet(b'C', 1, 0x81, 1_005_000, None, &[0x01, 0xff, 0x02]),
+            usbmon_packet(b'C', 1, 0x81, 2_005_500, None, &[0x00, 0x05, 0x00]),
+        ]
+    }
+
+    fn check_mouse(backend: &UsbmonBackend) {
+        assert_eq!(backend.rdesc(), &MOUSE);
+        assert_eq!(
+            backend
+                .events
+                .iter()
+                .map(|e| (e.usecs, e.bytes.clone()))
+                .collect::<Vec<(u64, Vec<u8>)>>(),
+            vec![
+                (0, vec![0x01, 0xff, 0x02]),
+                (1_000_500, vec![0x00, 0x05, 0x00])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_usbmon_text() {
+        assert!(is_usbmon_capture(TEXT.as_bytes()));
+        assert!(!is_usbmon_capture(b"05 01 09 02"));
+
+        let packets = TEXT
+            .lines()
+            .map(parse_text_line)
+            .collect::<Result<Vec<UrbPacket>>>()
+            .unwrap();
+        let backend = UsbmonBackend::from_packets(&packets, None).unwrap();
+        assert_eq!(backend.name(), "usbmon bus 1 device 4 interface 0");
+        assert_eq!((backend.vid(), backend.pid()), (0x046d, 0xc077));
+        assert_eq!(backend.rdesc().len(), 29);
+        assert_eq!(backend.events.len(), 2);
+        assert_eq!(backend.events[1].usecs, 8000);
+
+        // Longer report descriptors are truncated in the text format
+        let truncated = TEXT.replace("0 29 = 05010902", "0 50 = 05010902");
+        let packets = truncated
+            .lines()
+            .map(parse_text_line)
+            .collect::<Result<Vec<UrbPacket>>>()
+            .unwrap();
+        assert!(UsbmonBackend::from_packets(&packets, None).is_err());
+    }
+
+    #[test]
+    fn test_usbmon_pcap() {
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        pcap.extend([0u8; 8]);
+        pcap.extend(65535u32.to_le_bytes());
+        pcap.extend(LINKTYPE_USB_LINUX_MMAPPED.to_le_bytes());
+        for p in usbmon_packets() {
+            pcap.extend([0u8; 8]); // timestamp
+            pcap.extend((p.len() as u32).to_le_bytes());
+            pcap.extend((p.len() as u32).to_le_bytes());
+            pcap.extend(p);
+        }
+        assert!(is_usbmon_capture(&pcap));
+        let packets = parse_pcap(&pcap).unwrap();
+        let backend = UsbmonBackend::from_packets(&packets, None).unwrap();
+        assert_eq!(backend.name(), "Mou");
+        assert_eq!((backend.vid(), backend.pid()), (0x046d, 0xc077));
+        check_mouse(&backend);
+    }
+
+    #[test]
+    fn test_usbmon_pcapng() {
+        let block = |block_type: u32, body: &[u8]| -> Vec<u8> {
+            let mut body = body.to_vec();
+            body.resize((body.len() + 3) / 4 * 4, 0);
+            let len = (body.len() + 12) as u32;
+            [
+                block_type.to_le_bytes().as_slice(),
+                &len.to_le_bytes(),
+                &body,
+                &len.to_le_bytes(),
+            ]
+            .concat()
+        };
+        let mut shb = 0x1a2b3c4du32.to_le_bytes().to_vec();
+        shb.extend([1, 0, 0, 0]); // version
+        shb.extend(u64::MAX.to_le_bytes()); // section length
+        let mut idb = (LINKTYPE_USB_LINUX_MMAPPED as u16).to_le_bytes().to_vec();
+        idb.extend([0, 0, 0, 0, 0, 0]);
+
+        let mut pcapng = block(0x0a0d0d0a, &shb);
+        pcapng.extend(block(1, &idb));
+        for p in usbmon_packets() {
+            let mut epb = vec![0u8; 12]; // interface 0, timestamp
+            epb.extend((p.len() as u32).to_le_bytes());
+            epb.extend((p.len() as u32).to_le_bytes());
+            epb.extend(p);
+            pcapng.extend(block(6, &epb));
+        }
+        let packets = parse_pcapng(&pcapng).unwrap();
+        let backend = UsbmonBackend::from_packets(&packets, Some(0)).unwrap();
+        check_mouse(&backend);
+        assert!(UsbmonBackend::from_packets(&packets, Some(1)).is_err());
+    }
+}
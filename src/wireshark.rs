@@ -0,0 +1,449 @@
+// SPDX-License-Identifier: MIT
+//
+// Generate a Wireshark Lua dissector that decodes the input, output and
+// feature reports of a device field by field.
+//
+// The dissector registers itself for the device's vendor and product ID
+// in the usb.product dissector table and decodes interrupt transfers as
+// input or output reports and control transfers as feature reports.
+// Anything that does not match a report by its report ID and length is
+// left to Wireshark's own dissectors.
+
+use anyhow::Result;
+use hidreport::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::{get_hut_str, Backend};
+
+/// A string as Lua string literal
+fn lua_str(s: &str) -> String {
+    let mut escaped = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// The field name for a display filter, e.g. `resolution_multiplier`
+/// for "Resolution Multiplier"
+fn filter_name(s: &str) -> String {
+    let name = s
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>()
+        .join("_");
+    if name.is_empty() {
+        String::from("field")
+    } else {
+        name
+    }
+}
+
+/// The Lua ProtoField type for a value of this many bits
+fn protofield_type(bits: usize, signed: bool) -> String {
+    let size = match bits {
+        0..=8 => 8,
+        9..=16 => 16,
+        17..=24 => 24,
+        _ => 32,
+    };
+    format!("{}int{size}", if signed { "" } else { "u" })
+}
+
+/// Collects the ProtoFields and the per-report field tables
+#[derive(Default)]
+struct Dissector {
+    /// The ProtoField constructors, referenced as `pf[index + 1]`
+    protofields: Vec<String>,
+    /// The reports of each direction in Lua table syntax
+    reports: HashMap<&'static str, String>,
+}
+
+impl Dissector {
+    fn add_protofield(&mut self, protofield: String) -> usize {
+        self.protofields.push(protofield);
+        self.protofields.len()
+    }
+
+    fn add_report(&mut self, proto: &str, direction: &'static str, report: &impl Report) {
+        let report_id = report.report_id().map(u8::from);
+        let prefix = match report_id {
+            Some(id) => format!("{proto}.{direction}{id}"),
+            None => format!("{proto}.{direction}"),
+        };
+        let mut title = format!(
+            "{}{} Report",
+            &direction[..1].to_uppercase(),
+            &direction[1..]
+        );
+        if let Some(id) = report_id {
+            let _ = write!(title, " {id}");
+        }
+
+        // Filter names must be unique, the second "Button 1" in the same
+        // report is button_1_2
+        let mut names: HashMap<String, usize> = HashMap::new();
+        let mut unique_name = |name: &str| {
+            let name = filter_name(name);
+            let count = names.entry(name.clone()).or_default();
+            *count += 1;
+            match count {
+                1 => format!("{prefix}.{name}"),
+                n => format!("{prefix}.{name}_{n}"),
+            }
+        };
+
+        let mut fields = String::new();
+        for field in report.fields() {
+            let (name, bits, signed, lmin, lmax, count, values) = match field {
+                Field::Constant(_) => continue,
+                Field::Variable(v) => (
+                    get_hut_str(&v.usage),
+                    v.bits.clone(),
+                    v.is_signed(),
+                    i32::from(v.logical_minimum),
+                    i32::from(v.logical_maximum),
+                    1,
+                    None,
+                ),
+                Field::Array(a) => {
+                    let name = match (a.usages().first(), a.usages().last()) {
+                        (Some(first), Some(last)) if a.usages().len() > 1 => {
+                            format!("{}..={}", get_hut_str(first), get_hut_str(last))
+                        }
+                        (Some(first), _) => get_hut_str(first),
+                        _ => String::from("Array"),
+                    };
+                    // The array values are the index into the usages,
+                    // offset by the Logical Minimum
+                    let lmin = i32::from(a.logical_minimum);
+                    let values = a
+                        .usages()
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, usage)| {
+                            format!("[{}] = {}", lmin + idx as i32, lua_str(&get_hut_str(usage)))
+                        })
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    (
+                        name,
+                        a.bits.clone(),
+                        a.is_signed(),
+                        lmin,
+                        i32::from(a.logical_maximum),
+                        usize::from(a.report_count).max(1),
+                        Some(values),
+                    )
+                }
+            };
+            let size = bits.len() / count;
+            let protofield = if size > 32 {
+                format!(
+                    "ProtoField.bytes({}, {})",
+                    lua_str(&unique_name(&name)),
+                    lua_str(&name)
+                )
+            } else {
+                format!(
+                    "ProtoField.{}({}, {}, base.DEC{})",
+                    protofield_type(size, signed),
+                    lua_str(&unique_name(&name)),
+                    lua_str(&name),
+                    values.map(|v| format!(", {{ {v} }}")).unwrap_or_default()
+                )
+            };
+            let index = self.add_protofield(protofield);
+            for i in 0..count {
+                let _ = writeln!(
+                    fields,
+                    "                {{ field = pf[{index}], offset = {}, size = {size}, signed = {signed}, minimum = {lmin}, maximum = {lmax} }},",
+                    bits.start + i * size,
+                );
+            }
+        }
+
+        let _ = write!(
+            self.reports.entry(direction).or_default(),
+            r#"        [{key}] = {{
+            name = {title},
+            size = {size},
+            fields = {{
+{fields}            }},
+        }},
+"#,
+            key = report_id.unwrap_or(0),
+            title = lua_str(&title),
+            size = report.size_in_bytes(),
+        );
+    }
+}
+
+/// Generate the Lua source of a Wireshark dissector for the reports of
+/// the report descriptor in `backend`
+pub fn generate_dissector(backend: &impl Backend) -> Result<String> {
+    let rdesc = ReportDescriptor::try_from(backend.rdesc())?;
+    let (vid, pid) = (backend.vid(), backend.pid());
+    let proto = format!("hid_{vid:04x}_{pid:04x}");
+
+    let mut dissector = Dissector::default();
+    let report_id_index = dissector.add_protofield(format!(
+        "ProtoField.uint8({}, \"Report ID\", base.DEC)",
+        lua_str(&format!("{proto}.report_id"))
+    ));
+    for r in rdesc.input_reports() {
+        dissector.add_report(&proto, "input", r);
+    }
+    for r in rdesc.output_reports() {
+        dissector.add_report(&proto, "output", r);
+    }
+    for r in rdesc.feature_reports() {
+        dissector.add_report(&proto, "feature", r);
+    }
+    let uses_report_ids = [
+        rdesc
+            .input_reports()
+            .first()
+            .map(|r| r.report_id().is_some()),
+        rdesc
+            .output_reports()
+            .first()
+            .map(|r| r.report_id().is_some()),
+        rdesc
+            .feature_reports()
+            .first()
+            .map(|r| r.report_id().is_some()),
+    ]
+    .into_iter()
+    .flatten()
+    .any(|has_id| has_id);
+
+    let mut s = String::new();
+    let _ = write!(
+        s,
+        r#"-- SPDX-License-Identifier: MIT
+--
+-- Wireshark dissector for the HID reports of
+-- {device} ({vid:04x}:{pid:04x})
+--
+-- Generated by hid-recorder. Copy this file into the Wireshark plugin
+-- directory, e.g. ~/.local/lib/wireshark/plugins/, to load it.
+
+local hid_proto = Proto({proto_name}, {proto_description})
+
+local pf = {{
+"#,
+        device = backend.name(),
+        proto_name = lua_str(&proto),
+        proto_description = lua_str(&format!("HID reports of {}", backend.name())),
+    );
+    for protofield in &dissector.protofields {
+        let _ = writeln!(s, "    {protofield},");
+    }
+    let _ = write!(
+        s,
+        r#"}}
+hid_proto.fields = pf
+
+local out_of_range = ProtoExpert.new({expert_name}, "Value outside the logical range",
+    expert.group.PROTOCOL, expert.severity.WARN)
+hid_proto.experts = {{ out_of_range }}
+
+local uses_report_ids = {uses_report_ids}
+
+-- The reports keyed by their report ID, 0 without report IDs. Each field
+-- has its bit offset and size in the report.
+local reports = {{
+"#,
+        expert_name = lua_str(&format!("{proto}.out_of_range")),
+    );
+    for direction in ["input", "output", "feature"] {
+        let _ = write!(
+            s,
+            "    {direction} = {{\n{}    }},\n",
+            dissector
+                .reports
+                .get(direction)
+                .map(String::as_str)
+                .unwrap_or("")
+        );
+    }
+    let _ = write!(
+        s,
+        r#"}}
+
+-- The value of the `size` bits at bit `shift` of the little-endian range
+local function field_value(range, shift, size, signed)
+    local value
+    if range:len() > 4 then
+        value = range:le_uint64():tonumber()
+    else
+        value = range:le_uint()
+    end
+    value = math.floor(value / 2^shift) % 2^size
+    if signed and value >= 2^(size - 1) then
+        value = value - 2^size
+    end
+    return value
+end
+
+local function dissect_report(tvb, pinfo, tree, reports)
+    local report_id = 0
+    if uses_report_ids then
+        if tvb:len() < 1 then
+            return 0
+        end
+        report_id = tvb(0, 1):uint()
+    end
+    local report = reports[report_id]
+    if report == nil or tvb:len() < report.size then
+        return 0
+    end
+
+    pinfo.cols.protocol = hid_proto.name
+    local subtree = tree:add(hid_proto, tvb(), report.name)
+    if uses_report_ids then
+        subtree:add(pf[{report_id_index}], tvb(0, 1))
+    end
+    for _, f in ipairs(report.fields) do
+        local first = math.floor(f.offset / 8)
+        local last = math.floor((f.offset + f.size - 1) / 8)
+        local range = tvb(first, last - first + 1)
+        if f.size > 32 then
+            subtree:add(f.field, range)
+        else
+            local value = field_value(range, f.offset % 8, f.size, f.signed)
+            local item = subtree:add(f.field, range, value)
+            if value < f.minimum or value > f.maximum then
+                item:add_proto_expert_info(out_of_range,
+                    string.format("Value %d outside the logical range %d..%d",
+                        value, f.minimum, f.maximum))
+            end
+        end
+    end
+    return tvb:len()
+end
+
+local usb_transfer_type = Field.new("usb.transfer_type")
+local usb_direction = Field.new("usb.endpoint_address.direction")
+
+function hid_proto.dissector(tvb, pinfo, tree)
+    local transfer_type = usb_transfer_type()
+    if transfer_type == nil or tvb:len() == 0 then
+        return 0
+    end
+    if transfer_type.value == 0x01 then
+        -- Interrupt IN or OUT
+        local direction = usb_direction()
+        if direction ~= nil and direction.value == 1 then
+            return dissect_report(tvb, pinfo, tree, reports.input)
+        end
+        return dissect_report(tvb, pinfo, tree, reports.output)
+    elseif transfer_type.value == 0x02 then
+        -- GET_REPORT/SET_REPORT on the control endpoint
+        return dissect_report(tvb, pinfo, tree, reports.feature)
+    end
+    return 0
+end
+
+DissectorTable.get("usb.product"):add(0x{vid:04x}{pid:04x}, hid_proto)
+DissectorTable.get("usb.interrupt"):add_for_decode_as(hid_proto)
+"#,
+    );
+
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BpfOption, Event, EventNode};
+
+    // Keyboard with report ID 1: 8 modifier bits, 6 key array, 1 byte
+    // LED output; report ID 2: signed 12 bit vendor value and padding
+    const RDESC: [u8; 85] = [
+        0x05, 0x01, 0x09, 0x06, 0xa1, 0x01, 0x85, 0x01, 0x05, 0x07, 0x19, 0xe0, 0x29, 0xe7, 0x15,
+        0x00, 0x25, 0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02, 0x19, 0x00, 0x29, 0x65, 0x15, 0x00,
+        0x25, 0x65, 0x75, 0x08, 0x95, 0x06, 0x81, 0x00, 0x05, 0x08, 0x19, 0x01, 0x29, 0x03, 0x25,
+        0x01, 0x75, 0x01, 0x95, 0x03, 0x91, 0x02, 0x95, 0x05, 0x91, 0x01, 0xc0, 0x06, 0x00, 0xff,
+        0x09, 0x01, 0xa1, 0x01, 0x85, 0x02, 0x16, 0x00, 0xf8, 0x26, 0xff, 0x07, 0x75, 0x0c, 0x95,
+        0x01, 0x09, 0x02, 0x81, 0x02, 0x75, 0x04, 0x81, 0x01, 0xc0,
+    ];
+
+    struct Keyboard(Vec<u8>);
+
+    impl Backend for Keyboard {
+        fn name(&self) -> &str {
+            "Test \"Keyboard\""
+        }
+        fn bustype(&self) -> u32 {
+            0x03
+        }
+        fn vid(&self) -> u32 {
+            0x046d
+        }
+        fn pid(&self) -> u32 {
+            0xc31c
+        }
+        fn rdesc(&self) -> &[u8] {
+            &self.0
+        }
+        fn event_nodes(&self) -> &[EventNode] {
+            &[]
+        }
+        fn read_events(
+            &self,
+            _use_bpf: BpfOption,
+            _rdesc: &ReportDescriptor,
+            _callback: &mut dyn FnMut(Event) -> anyhow::Result<()>,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_dissector() {
+        assert_eq!(lua_str("a \"b\" \\"), "\"a \\\"b\\\" \\\\\"");
+        assert_eq!(filter_name("Keyboard LeftControl"), "keyboard_leftcontrol");
+
+        let lua = generate_dissector(&Keyboard(RDESC.to_vec())).unwrap();
+        assert!(lua.contains(
+            r#"local hid_proto = Proto("hid_046d_c31c", "HID reports of Test \"Keyboard\"")"#
+        ));
+        assert!(lua.contains("local uses_report_ids = true"));
+        assert!(lua.contains(r#"ProtoField.uint8("hid_046d_c31c.input1.keyboard_leftcontrol", "Keyboard LeftControl", base.DEC)"#));
+        assert!(lua.contains(
+            r#"ProtoField.uint8("hid_046d_c31c.output1.num_lock", "Num Lock", base.DEC)"#
+        ));
+        // Signed 12 bit vendor field
+        assert!(
+            lua.contains("offset = 8, size = 12, signed = true, minimum = -2048, maximum = 2047")
+        );
+        assert!(lua.contains("DissectorTable.get(\"usb.product\"):add(0x046dc31c, hid_proto)"));
+
+        // The key array has 6 entries of the same field with the usages
+        // as value strings
+        let array = lua
+            .lines()
+            .find(|l| l.contains("[1] = \"ErrorRollOver\""))
+            .unwrap();
+        assert!(array.contains("ProtoField.uint8(\"hid_046d_c31c.input1."));
+        let pf = lua.lines().position(|l| l == "local pf = {").unwrap();
+        let index = lua.lines().position(|l| l == array).unwrap() - pf;
+        let array_fields: Vec<&str> = lua
+            .lines()
+            .filter(|l| l.contains(&format!("field = pf[{index}],")))
+            .collect();
+        assert_eq!(array_fields.len(), 6);
+        assert!(array_fields[5].contains("offset = 56, size = 8"));
+    }
+}